@@ -3,24 +3,51 @@ struct VertexOutput {
     @location(0) world_position: vec4<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
+    @location(3) occlusion: f32,
 }
 
+struct PaletteLayout {
+    columns: u32,
+    len: u32,
+}
+
+struct Lighting {
+    sun_direction: vec3<f32>,
+    sun_intensity: f32,
+    ambient: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> palette_layout: PaletteLayout;
 @group(1) @binding(1)
 var palette: texture_2d<f32>;
 @group(1) @binding(2)
-var palette_sampler: sampler;
+var<uniform> light: Lighting;
 
-let light_direction = vec3<f32>(0.0, 0.917, 0.4);
+// How much darker mortar between tiles is than the tiles themselves
+let mortar_shade = 0.6;
+// How much darker fully occluded corners are than open surfaces
+let occlusion_shade = 0.5;
 
+// The palette index is stored in the x coordinate of the uv, and the y
+// coordinate is 1 for mortar
 fn color(uv: vec2<f32>) -> vec4<f32> {
-    return textureSample(palette, palette_sampler, uv);
+    let index = u32(uv.x + 0.5);
+    if index >= palette_layout.len {
+        // Missing colors are magenta so they stand out
+        return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+    }
+    let pos = vec2<i32>(i32(index % palette_layout.columns), i32(index / palette_layout.columns));
+    let base = textureLoad(palette, pos, 0);
+    return vec4<f32>(base.rgb * mix(1.0, mortar_shade, uv.y), base.a);
 }
 
 fn lighting(normal: vec3<f32>) -> f32 {
-    return 0.8 + abs(dot(normal, light_direction)) * 0.2;
+    return light.ambient + abs(dot(normal, light.sun_direction)) * light.sun_intensity;
 }
 
 @fragment
 fn fragment(input: VertexOutput) -> @location(0) vec4<f32> {
-    return color(input.uv) * lighting(input.world_normal);
+    let occlusion = mix(1.0, occlusion_shade, input.occlusion);
+    return color(input.uv) * lighting(input.world_normal) * occlusion;
 }
@@ -7,7 +7,11 @@ mod terrain;
 
 fn main() {
     App::new()
-        .add_plugins(DefaultPlugins)
+        .add_plugins(DefaultPlugins.set(AssetPlugin {
+            // Lets palettes be edited while running
+            watch_for_changes: true,
+            ..default()
+        }))
         .add_plugin(EguiPlugin)
         .add_plugin(camera::CameraPlugin)
         .add_plugin(terrain::TerrainPlugin)
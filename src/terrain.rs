@@ -4,15 +4,22 @@ use bevy::{
     prelude::*,
     utils::{HashMap, HashSet},
 };
+use itertools::iproduct;
 
 use self::{
     chunk::{Chunk, ChunkPos, Cleanup, LocalPos, TileSlot, CHUNK_WIDTH},
+    history::Edit,
     tile::Tile,
 };
 
 mod chunk;
+mod generate;
+mod history;
 mod inspect;
 mod mesh;
+mod palette;
+mod pick;
+mod save;
 mod tile;
 
 pub struct TerrainPlugin;
@@ -20,7 +27,11 @@ pub struct TerrainPlugin;
 impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(inspect::InspectPlugin)
+            .add_plugin(generate::GeneratePlugin)
+            .add_plugin(history::HistoryPlugin)
             .add_plugin(mesh::MeshPlugin)
+            .add_plugin(palette::PalettePlugin)
+            .add_plugin(pick::PickPlugin)
             .init_resource::<Terrain>();
     }
 }
@@ -29,7 +40,9 @@ impl Plugin for TerrainPlugin {
 pub struct Terrain {
     chunks: HashMap<ChunkPos, Chunk>,
     changed: HashSet<ChunkPos>,
-    mesh_ids: HashMap<ChunkPos, (Entity, Handle<Mesh>)>,
+    mesh_ids: HashMap<ChunkPos, mesh::ChunkMeshes>,
+    /// Edits made while recording, see `History::record`
+    recording: Option<Vec<Edit>>,
 }
 
 impl Terrain {
@@ -41,14 +54,18 @@ impl Terrain {
 
     pub fn set(&mut self, pos: GlobalPos, tile: Tile) {
         let chunk = self.chunks.entry(pos.chunk).or_default();
+        let before = chunk[pos.local];
         let cleanup = chunk.set(pos.local, tile);
-        self.cleanup(pos, cleanup);
+        self.record(pos, before, Some(tile));
+        self.cleanup(pos.chunk, pos.local, pos.local, cleanup);
     }
 
     pub fn remove(&mut self, pos: GlobalPos) {
         if let Some(chunk) = self.chunks.get_mut(&pos.chunk) {
+            let before = chunk[pos.local];
             let cleanup = chunk.remove(pos.local);
-            self.cleanup(pos, cleanup);
+            self.record(pos, before, None);
+            self.cleanup(pos.chunk, pos.local, pos.local, cleanup);
         }
     }
 
@@ -59,48 +76,144 @@ impl Terrain {
         }
     }
 
-    fn cleanup(&mut self, pos: GlobalPos, cleanup: Cleanup) {
-        self.mark_changed(pos);
-        match cleanup {
-            Cleanup::None => {}
-            Cleanup::RemoveChunk => drop(self.chunks.remove(&pos.chunk)),
-        }
+    /// Sets every tile in the box between corners `a` and `b` (inclusive)
+    pub fn fill_box(&mut self, a: GlobalPos, b: GlobalPos, tile: Tile) {
+        self.edit_box(a, b, |_| Some(tile));
+    }
+
+    /// Replaces every `from` in the box between corners `a` and `b`
+    /// (inclusive) with `to`
+    pub fn replace_in_box(&mut self, a: GlobalPos, b: GlobalPos, from: TileSlot, to: TileSlot) {
+        self.edit_box(a, b, |slot| if slot == from { to } else { slot });
+    }
+
+    /// Turns every tile in the box between corners `a` and `b` (inclusive) a
+    /// quarter turn around `axis`, leaving them where they are
+    pub fn turn_in_box(&mut self, a: GlobalPos, b: GlobalPos, axis: IVec3) {
+        self.edit_box(a, b, |slot| slot.map(|tile| tile.rotated(axis)));
     }
 
-    fn mark_changed(&mut self, pos: GlobalPos) {
-        self.changed.insert(pos.chunk);
+    /// Removes every tile in the box between corners `a` and `b` (inclusive)
+    pub fn clear_box(&mut self, a: GlobalPos, b: GlobalPos) {
+        self.edit_box(a, b, |_| None);
+    }
 
-        fn insert_1(s: &mut Terrain, p: GlobalPos, a: ChunkPos) {
-            s.changed.insert(p.chunk - a);
+    /// Applies `edit` to every slot in a box one chunk at a time, marking each
+    /// changed chunk once
+    fn edit_box(&mut self, a: GlobalPos, b: GlobalPos, edit: impl Fn(TileSlot) -> TileSlot) {
+        let fills_air = edit(None).is_some();
+        self.edit_box_at(a, b, fills_air, |_, slot| edit(slot));
+    }
+
+    /// Like `edit_box`, but `edit` is also given the position of each slot.
+    /// Chunks that do not exist yet are skipped unless `fills_air` is set
+    pub fn edit_box_at(
+        &mut self,
+        a: GlobalPos,
+        b: GlobalPos,
+        fills_air: bool,
+        edit: impl Fn(GlobalPos, TileSlot) -> TileSlot,
+    ) {
+        let (a, b) = (a.xyz(), b.xyz());
+        let min = GlobalPos::from_xyz([0, 1, 2].map(|i| a[i].min(b[i])));
+        let max = GlobalPos::from_xyz([0, 1, 2].map(|i| a[i].max(b[i])));
+
+        for (x, y, z) in iproduct!(
+            min.chunk.x..=max.chunk.x,
+            min.chunk.y..=max.chunk.y,
+            min.chunk.z..=max.chunk.z
+        ) {
+            let chunk_pos = ChunkPos::new(x, y, z);
+            let bound = |pos: GlobalPos, default: u8| {
+                let [x, y, z] = [
+                    (x, pos.chunk.x, pos.local.x()),
+                    (y, pos.chunk.y, pos.local.y()),
+                    (z, pos.chunk.z, pos.local.z()),
+                ]
+                .map(|(c, pos_c, local)| if c == pos_c { local } else { default });
+                LocalPos::new([x, y, z]).unwrap()
+            };
+            let lo = bound(min, 0);
+            let hi = bound(max, CHUNK_WIDTH - 1);
+
+            if !fills_air && !self.chunks.contains_key(&chunk_pos) {
+                continue;
+            }
+            let chunk = self.chunks.entry(chunk_pos).or_default();
+            let recording = &mut self.recording;
+            let (changed, cleanup) = chunk.edit_box(lo, hi, |local, before| {
+                let pos = GlobalPos {
+                    chunk: chunk_pos,
+                    local,
+                };
+                let after = edit(pos, before);
+                if let Some(edits) = recording {
+                    edits.extend(Edit::new(pos, before, after));
+                }
+                after
+            });
+            if changed {
+                self.cleanup(chunk_pos, lo, hi, cleanup);
+            } else if chunk.is_empty() {
+                // Added above but nothing was put in it
+                self.chunks.remove(&chunk_pos);
+            }
         }
-        fn insert_2(s: &mut Terrain, p: GlobalPos, a: ChunkPos, b: ChunkPos) {
-            s.changed.insert(p.chunk - a);
-            s.changed.insert(p.chunk - b);
-            s.changed.insert(p.chunk - a - b);
+    }
+
+    fn record(&mut self, pos: GlobalPos, before: TileSlot, after: TileSlot) {
+        if let Some(edits) = &mut self.recording {
+            edits.extend(Edit::new(pos, before, after));
         }
-        fn insert_3(s: &mut Terrain, p: GlobalPos, a: ChunkPos, b: ChunkPos, c: ChunkPos) {
-            s.changed.insert(p.chunk - a);
-            s.changed.insert(p.chunk - b);
-            s.changed.insert(p.chunk - c);
-            s.changed.insert(p.chunk - a - b);
-            s.changed.insert(p.chunk - a - c);
-            s.changed.insert(p.chunk - b - c);
-            s.changed.insert(p.chunk - a - b - c);
+    }
+
+    /// Marks the tiles between `lo` and `hi` in the chunk at `chunk` as
+    /// changed, then removes the chunk if `cleanup` says to
+    fn cleanup(&mut self, chunk: ChunkPos, lo: LocalPos, hi: LocalPos, cleanup: Cleanup) {
+        self.mark_changed(chunk, lo, hi);
+        match cleanup {
+            Cleanup::None => {}
+            Cleanup::RemoveChunk => drop(self.chunks.remove(&chunk)),
         }
+    }
 
-        match pos.local.xyz().map(|v| v == 0) {
-            [false, false, false] => {}
-            [true, false, false] => insert_1(self, pos, ChunkPos::X),
-            [false, true, false] => insert_1(self, pos, ChunkPos::Y),
-            [false, false, true] => insert_1(self, pos, ChunkPos::Z),
-            [true, true, false] => insert_2(self, pos, ChunkPos::X, ChunkPos::Y),
-            [true, false, true] => insert_2(self, pos, ChunkPos::X, ChunkPos::Z),
-            [false, true, true] => insert_2(self, pos, ChunkPos::Y, ChunkPos::Z),
-            [true, true, true] => insert_3(self, pos, ChunkPos::X, ChunkPos::Y, ChunkPos::Z),
+    /// Marks the chunk at `chunk` as changed, along with any chunks whose
+    /// meshes include the tiles between `lo` and `hi` in it
+    fn mark_changed(&mut self, chunk: ChunkPos, lo: LocalPos, hi: LocalPos) {
+        // Meshes include the tiles just after their chunk, and the tiles just
+        // before it where it meets chunks at a different level of detail
+        let (lo, hi) = (lo.xyz(), hi.xyz());
+        let range = |i: usize| -i32::from(lo[i] == 0)..=i32::from(hi[i] == CHUNK_WIDTH - 1);
+        for (x, y, z) in iproduct!(range(0), range(1), range(2)) {
+            self.changed.insert(chunk + ChunkPos::new(x, y, z));
         }
     }
 
+    /// Marks every tile in the chunk at `pos` as changed
+    fn mark_chunk_changed(&mut self, pos: ChunkPos) {
+        self.mark_changed(pos, LocalPos::ZERO, LocalPos::MAX);
+    }
+
+    /// Number of chunks and bytes of memory used by them
+    pub fn memory_usage(&self) -> (usize, usize) {
+        let bytes = self
+            .chunks
+            .values()
+            .map(|chunk| std::mem::size_of::<Chunk>() + chunk.heap_size())
+            .sum();
+        (self.chunks.len(), bytes)
+    }
+
     pub fn clear(&mut self) {
+        if let Some(edits) = &mut self.recording {
+            for (&chunk, tiles) in &self.chunks {
+                edits.extend(
+                    LocalPos::positions().filter_map(|local| {
+                        Edit::new(GlobalPos { chunk, local }, tiles[local], None)
+                    }),
+                );
+            }
+        }
         self.chunks.clear();
         self.changed.clear();
         self.changed.extend(self.mesh_ids.keys());
@@ -138,6 +251,21 @@ impl GlobalPos {
         }
     }
 
+    /// Center of the tile in world space.  The tile at `pos` fills the unit cube
+    /// from `pos - 1` to `pos`
+    pub fn center(self) -> Vec3 {
+        Vec3::new(self.x() as f32, self.y() as f32, self.z() as f32) - Vec3::splat(0.5)
+    }
+
+    pub fn offset(self, offset: IVec3) -> GlobalPos {
+        let [x, y, z] = self.xyz();
+        GlobalPos::from_xyz([
+            x + offset.x as i64,
+            y + offset.y as i64,
+            z + offset.z as i64,
+        ])
+    }
+
     pub fn from_xyz_i32(xyz: impl Into<IVec3>) -> GlobalPos {
         let xyz = xyz.into();
         Self {
@@ -172,12 +300,16 @@ impl Display for GlobalPos {
 
 #[cfg(test)]
 mod tests {
+    use itertools::iproduct;
     use proptest::prelude::*;
 
-    use super::{tile::Tile, GlobalPos, Terrain};
+    use super::{
+        tile::Tile,
+        GlobalPos, Terrain,
+    };
 
     prop_compose! {
-        fn arb_global_pos()(xyz: [i32; 3]) -> GlobalPos {
+        pub(super) fn arb_global_pos()(xyz: [i32; 3]) -> GlobalPos {
             GlobalPos::from_xyz_i32(xyz)
         }
     }
@@ -234,6 +366,72 @@ mod tests {
             assert_eq!(terrain.chunks.len(), 0);
         }
 
+        #[test]
+        fn fill_box_matches_set(a in [-12..12, -12..12, -12..12], b in [-12..12, -12..12, -12..12]) {
+            let mut filled = Terrain::default();
+            filled.fill_box(GlobalPos::from_xyz_i32(a), GlobalPos::from_xyz_i32(b), Tile::BRICK);
+
+            let mut set = Terrain::default();
+            for (x, y, z) in iproduct!(
+                a[0].min(b[0])..=a[0].max(b[0]),
+                a[1].min(b[1])..=a[1].max(b[1]),
+                a[2].min(b[2])..=a[2].max(b[2])
+            ) {
+                set.set(GlobalPos::from_xyz_i32([x, y, z]), Tile::BRICK);
+            }
+
+            assert_eq!(filled.chunks, set.chunks);
+            assert_eq!(filled.changed, set.changed);
+        }
+
+        #[test]
+        fn fill_clear_box(a in [-24..24, -24..24, -24..24], b in [-24..24, -24..24, -24..24]) {
+            let (a, b) = (GlobalPos::from_xyz_i32(a), GlobalPos::from_xyz_i32(b));
+            let mut terrain = Terrain::default();
+
+            terrain.fill_box(a, b, Tile::BRICK);
+            assert_eq!(terrain.get(a), Some(Tile::BRICK));
+            assert_eq!(terrain.get(b), Some(Tile::BRICK));
+
+            terrain.clear_box(b, a);
+            assert_eq!(terrain.chunks.len(), 0);
+        }
+
+        #[test]
+        fn replace_in_box(
+            poses in proptest::collection::vec([-8..8, -8..8, -8..8], 1..64),
+            a in [-8..8, -8..8, -8..8],
+            b in [-8..8, -8..8, -8..8],
+        ) {
+            let poses = poses
+                .into_iter()
+                .map(GlobalPos::from_xyz_i32)
+                .collect::<Vec<_>>();
+            let (a, b) = (GlobalPos::from_xyz_i32(a), GlobalPos::from_xyz_i32(b));
+            let glass = Tile::GLASS;
+            let mut terrain = Terrain::default();
+            for &pos in &poses {
+                terrain.set(pos, Tile::BRICK);
+            }
+
+            terrain.replace_in_box(a, b, Some(Tile::BRICK), Some(glass));
+            let in_box = |pos: GlobalPos| {
+                (0..3).all(|i| {
+                    let (a, b, pos) = (a.xyz()[i], b.xyz()[i], pos.xyz()[i]);
+                    a.min(b) <= pos && pos <= a.max(b)
+                })
+            };
+            for &pos in &poses {
+                let expected = if in_box(pos) { glass } else { Tile::BRICK };
+                assert_eq!(terrain.get(pos), Some(expected));
+            }
+
+            terrain.replace_in_box(a, b, Some(glass), None);
+            for &pos in &poses {
+                assert_eq!(terrain.get(pos).is_some(), !in_box(pos));
+            }
+        }
+
         #[test]
         fn global_pos_xyz(xyz: [i32; 3]) {
             let global_pos = GlobalPos::from_xyz_i32(xyz);
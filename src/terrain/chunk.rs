@@ -1,9 +1,10 @@
 use std::{
     fmt::{Debug, Display},
-    ops::{BitAnd, Index},
+    ops::Index,
 };
 
 use bevy::prelude::*;
+use itertools::iproduct;
 
 use super::tile::Tile;
 
@@ -14,52 +15,191 @@ pub type ChunkPos = IVec3;
 
 pub type TileSlot = Option<Tile>;
 
-#[derive(Debug)]
+/// Tiles are stored as indices into a palette of every distinct slot in the
+/// chunk, so chunks that are mostly empty or uniform stay small
+#[derive(Debug, Clone)]
 pub struct Chunk {
-    data: [TileSlot; CHUNK_AREA],
+    palette: Vec<PaletteEntry>,
+    indices: PackedIndices,
     set_tiles: u16,
 }
 
-macro_rules! slot {
-    ($chunk:ident, $pos:ident) => {
-        &mut $chunk.data[$pos.0 as usize]
-    };
+#[derive(Debug, Clone, Copy)]
+struct PaletteEntry {
+    slot: TileSlot,
+    /// How many positions in the chunk use this entry.  Entries with a count
+    /// of zero are reused before the palette grows
+    count: u16,
 }
 
 impl Chunk {
     pub fn set(&mut self, pos: LocalPos, tile: Tile) -> Cleanup {
-        let slot = slot!(self, pos);
-        if slot.is_none() {
+        if self.replace(pos, Some(tile)).is_none() {
             self.set_tiles += 1;
         }
-        *slot = Some(tile);
         Cleanup::None
     }
 
     pub fn remove(&mut self, pos: LocalPos) -> Cleanup {
-        let slot = slot!(self, pos);
-        if slot.is_some() {
+        if self.replace(pos, None).is_some() {
             self.set_tiles -= 1;
         }
-        *slot = None;
         Cleanup::remove_if_zero(self.set_tiles)
     }
+
+    /// Replaces every slot in the box between `lo` and `hi` (inclusive) with
+    /// the result of `edit`.  Returns whether any slot changed
+    pub fn edit_box(
+        &mut self,
+        lo: LocalPos,
+        hi: LocalPos,
+        mut edit: impl FnMut(LocalPos, TileSlot) -> TileSlot,
+    ) -> (bool, Cleanup) {
+        let mut changed = false;
+        for pos in LocalPos::box_positions(lo, hi) {
+            let old = self[pos];
+            let new = edit(pos, old);
+            if new != old {
+                changed = true;
+                let _ = match new {
+                    Some(tile) => self.set(pos, tile),
+                    None => self.remove(pos),
+                };
+            }
+        }
+        (changed, Cleanup::remove_if_zero(self.set_tiles))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set_tiles == 0
+    }
+
+    /// Bytes of heap memory used by self
+    pub fn heap_size(&self) -> usize {
+        self.palette.capacity() * std::mem::size_of::<PaletteEntry>()
+            + self.indices.words.capacity() * std::mem::size_of::<u64>()
+    }
+
+    /// Sets the slot at `pos`, returning what was there before
+    fn replace(&mut self, pos: LocalPos, slot: TileSlot) -> TileSlot {
+        let old_index = self.indices.get(pos.0);
+        let old = &mut self.palette[old_index as usize];
+        if old.slot == slot {
+            return slot;
+        }
+        old.count -= 1;
+        let old_slot = old.slot;
+
+        let new_index = self.palette_index(slot);
+        self.palette[new_index as usize].count += 1;
+        self.indices.set(pos.0, new_index);
+        old_slot
+    }
+
+    /// Finds or inserts a palette entry for `slot`
+    fn palette_index(&mut self, slot: TileSlot) -> u16 {
+        if let Some(index) = self
+            .palette
+            .iter()
+            .position(|e| e.slot == slot && e.count > 0)
+        {
+            return index as u16;
+        }
+        let entry = PaletteEntry { slot, count: 0 };
+        if let Some(index) = self.palette.iter().position(|e| e.count == 0) {
+            self.palette[index] = entry;
+            return index as u16;
+        }
+        self.palette.push(entry);
+        self.indices.fit(self.palette.len());
+        (self.palette.len() - 1) as u16
+    }
 }
 
 impl Default for Chunk {
     fn default() -> Self {
         Self {
-            data: [None; CHUNK_AREA],
+            palette: vec![PaletteEntry {
+                slot: None,
+                count: CHUNK_AREA as u16,
+            }],
+            indices: PackedIndices::default(),
             set_tiles: 0,
         }
     }
 }
 
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.set_tiles == other.set_tiles
+            && LocalPos::positions().all(|pos| self[pos] == other[pos])
+    }
+}
+
+impl Eq for Chunk {}
+
 impl Index<LocalPos> for Chunk {
     type Output = TileSlot;
 
     fn index(&self, index: LocalPos) -> &Self::Output {
-        &self.data[index.0 as usize]
+        &self.palette[self.indices.get(index.0) as usize].slot
+    }
+}
+
+/// One palette index per position in a chunk, packed into words `bits` at a
+/// time.  With zero bits every index is 0 and nothing is allocated
+#[derive(Debug, Default, Clone)]
+struct PackedIndices {
+    bits: u8,
+    words: Vec<u64>,
+}
+
+impl PackedIndices {
+    fn get(&self, i: u16) -> u16 {
+        if self.bits == 0 {
+            return 0;
+        }
+        let (word, shift) = self.locate(i);
+        ((self.words[word] >> shift) & self.mask()) as u16
+    }
+
+    fn set(&mut self, i: u16, index: u16) {
+        debug_assert!((index as u64) <= self.mask());
+        let (word, shift) = self.locate(i);
+        let mask = self.mask();
+        let word = &mut self.words[word];
+        *word = (*word & !(mask << shift)) | ((index as u64) << shift);
+    }
+
+    /// Repacks self so it can hold indices into a palette of length `len`.
+    /// Bit widths are powers of two so indices never straddle words
+    fn fit(&mut self, len: usize) {
+        let mut bits = self.bits.max(1);
+        while 1 << bits < len {
+            bits *= 2;
+        }
+        if bits == self.bits {
+            return;
+        }
+
+        let mut repacked = PackedIndices {
+            bits,
+            words: vec![0; CHUNK_AREA / (64 / bits as usize)],
+        };
+        for i in 0..CHUNK_AREA as u16 {
+            repacked.set(i, self.get(i));
+        }
+        *self = repacked;
+    }
+
+    fn locate(&self, i: u16) -> (usize, u32) {
+        let per_word = 64 / self.bits as usize;
+        let i = i as usize;
+        (i / per_word, ((i % per_word) * self.bits as usize) as u32)
+    }
+
+    fn mask(&self) -> u64 {
+        (1 << self.bits) - 1
     }
 }
 
@@ -85,19 +225,8 @@ pub struct LocalPos(u16);
 
 impl LocalPos {
     pub const ZERO: Self = Self(0);
-
-    /// Bitmask that preserves x position of self
-    pub const X_MASK: Self = Self(0xf00);
-    /// Bitmask that preserves y position of self
-    pub const Y_MASK: Self = Self(0x0f0);
-    /// Bitmask that preserves z position of self
-    pub const Z_MASK: Self = Self(0x00f);
-    /// Bitmask that preserves x and y position of self
-    pub const XY_MASK: Self = Self(0xff0);
-    /// Bitmask that preserves x and z position of self
-    pub const XZ_MASK: Self = Self(0xf0f);
-    /// Bitmask that preserves y and z position of self
-    pub const YZ_MASK: Self = Self(0x0ff);
+    /// The last position in a chunk, furthest along every axis
+    pub const MAX: Self = Self(0x0fff);
 
     /// Constructs a new local pos
     ///
@@ -118,6 +247,13 @@ impl LocalPos {
         Self(x | y | z)
     }
 
+    /// Constructs a new local pos, or returns `None` if it is outside the chunk
+    pub fn from_ivec3(pos: IVec3) -> Option<Self> {
+        let inside =
+            pos.cmpge(IVec3::ZERO).all() && pos.cmplt(IVec3::splat(CHUNK_WIDTH.into())).all();
+        inside.then(|| Self::new_unchecked(pos.to_array().map(|i| i as u8)))
+    }
+
     /// Tries to construct self from bit representation
     /// Layout (bits): 0000xxxxyyyyzzzz
     pub fn try_from_bits(bits: u16) -> Option<Self> {
@@ -165,6 +301,17 @@ impl LocalPos {
         Self(self.0 + 0x0001)
     }
 
+    /// Every position in a chunk, in bit order
+    pub fn positions() -> impl Iterator<Item = LocalPos> {
+        (0..CHUNK_AREA as u16).map(LocalPos)
+    }
+
+    /// Every position in the box between `lo` and `hi` (inclusive)
+    pub fn box_positions(lo: LocalPos, hi: LocalPos) -> impl Iterator<Item = LocalPos> {
+        iproduct!(lo.x()..=hi.x(), lo.y()..=hi.y(), lo.z()..=hi.z())
+            .map(|(x, y, z)| LocalPos::new_unchecked([x, y, z]))
+    }
+
     pub const fn inner_positions() -> impl Iterator<Item = LocalPos> {
         #[derive(Debug)]
         pub struct Iter(u16);
@@ -202,21 +349,13 @@ impl Display for LocalPos {
     }
 }
 
-impl BitAnd for LocalPos {
-    type Output = Self;
-
-    fn bitand(self, rhs: Self) -> Self::Output {
-        Self(self.0 & rhs.0)
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use proptest::prelude::*;
 
-    use crate::terrain::tile::Tile;
+    use crate::terrain::tile::{color::IndexedColor, orientation::Orientation, shape::Shape, Tile};
 
-    use super::{Chunk, Cleanup, LocalPos, CHUNK_WIDTH};
+    use super::{Chunk, Cleanup, LocalPos, TileSlot, CHUNK_AREA, CHUNK_WIDTH};
 
     prop_compose! {
         fn arb_local_pos()(xyz in [..CHUNK_WIDTH; 3]) -> LocalPos {
@@ -252,6 +391,46 @@ mod tests {
             assert_eq!(cleanup, Cleanup::RemoveChunk);
         }
 
+        #[test]
+        fn chunk_matches_dense_array(
+            edits in proptest::collection::vec(
+                (arb_local_pos(), proptest::option::of(0..=IndexedColor::MAX_INDEX)),
+                1..256,
+            )
+        ) {
+            let mut chunk = Chunk::default();
+            let mut dense: [TileSlot; CHUNK_AREA] = [None; CHUNK_AREA];
+            let mut set_tiles = 0;
+
+            for (pos, color) in edits {
+                let slot = color
+                    .and_then(IndexedColor::from_index)
+                    .map(|color| Tile::Brick {
+                        color,
+                        orientation: Orientation::DEFAULT,
+                        shape: Shape::Cube,
+                    });
+                let cleanup = match slot {
+                    Some(tile) => chunk.set(pos, tile),
+                    None => chunk.remove(pos),
+                };
+                let dense_slot = &mut dense[pos.bits() as usize];
+                match (dense_slot.is_some(), slot.is_some()) {
+                    (false, true) => set_tiles += 1,
+                    (true, false) => set_tiles -= 1,
+                    _ => {}
+                }
+                *dense_slot = slot;
+
+                assert_eq!(cleanup == Cleanup::RemoveChunk, set_tiles == 0 && slot.is_none());
+                assert_eq!(chunk.is_empty(), set_tiles == 0);
+            }
+
+            for pos in LocalPos::positions() {
+                assert_eq!(chunk[pos], dense[pos.bits() as usize]);
+            }
+        }
+
         #[test]
         fn local_pos_new_doesnt_panic(xyz in [..CHUNK_WIDTH; 3]) {
             LocalPos::new(xyz).unwrap();
@@ -278,6 +457,83 @@ mod tests {
         }
     }
 
+    /// Compares chunk storage to a dense array of slots, printing the memory
+    /// each uses and the average time to fill and then read every tile of a
+    /// chunk.  Palette storage should use a fraction of the memory of the
+    /// dense array, at the cost of being several times slower.  The crate
+    /// has no library target for `benches/` to link against, so run it with
+    /// `cargo test --release bench_chunk_storage -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_chunk_storage() {
+        use std::{hint::black_box, mem::size_of, time::Instant};
+
+        fn brick(i: usize) -> Tile {
+            Tile::Brick {
+                color: IndexedColor::from_index((i % 62) as u8).unwrap(),
+                orientation: Orientation::DEFAULT,
+                shape: Shape::Cube,
+            }
+        }
+        type TileAt = fn(usize) -> TileSlot;
+        let cases: [(&str, TileAt); 4] = [
+            ("single brick", |i| (i == 0).then_some(Tile::BRICK)),
+            ("half filled", |i| {
+                (i < CHUNK_AREA / 2).then_some(Tile::BRICK)
+            }),
+            ("filled", |_| Some(Tile::BRICK)),
+            ("noisy", |i| (i % 3 != 0).then(|| brick(i * 7))),
+        ];
+        const RUNS: u32 = 100;
+
+        for (name, tile_at) in cases {
+            let start = Instant::now();
+            let mut chunk = Chunk::default();
+            for _ in 0..RUNS {
+                chunk = Chunk::default();
+                for pos in LocalPos::positions() {
+                    if let Some(tile) = tile_at(pos.bits() as usize) {
+                        let _ = chunk.set(pos, tile);
+                    }
+                }
+                for pos in LocalPos::positions() {
+                    black_box(chunk[pos]);
+                }
+            }
+            let palette_time = start.elapsed() / RUNS;
+
+            let start = Instant::now();
+            for _ in 0..RUNS {
+                let mut dense = Box::new([None; CHUNK_AREA]);
+                for (i, slot) in dense.iter_mut().enumerate() {
+                    *slot = tile_at(i);
+                }
+                for slot in dense.iter() {
+                    black_box(slot);
+                }
+            }
+            let dense_time = start.elapsed() / RUNS;
+
+            let palette_size = size_of::<Chunk>() + chunk.heap_size();
+            let dense_size = size_of::<[TileSlot; CHUNK_AREA]>();
+            println!(
+                "{name:>12}: palette {palette_size:>6} bytes {palette_time:>10?} | dense {dense_size:>6} bytes {dense_time:>10?}",
+            );
+            for pos in LocalPos::positions() {
+                assert_eq!(chunk[pos], tile_at(pos.bits() as usize));
+            }
+            assert!(
+                palette_size < dense_size,
+                "{name} uses more memory than a dense array"
+            );
+        }
+    }
+
+    #[test]
+    fn pos_correct_count() {
+        assert_eq!(LocalPos::positions().count(), CHUNK_AREA)
+    }
+
     #[test]
     fn inner_pos_correct_count() {
         assert_eq!(LocalPos::inner_positions().count(), 15_usize.pow(3))
@@ -0,0 +1,61 @@
+use std::ops::RangeInclusive;
+
+use bevy::prelude::*;
+
+use self::{
+    city::CitySettings,
+    ground::{GroundQueue, GroundSettings},
+};
+
+pub mod city;
+pub mod ground;
+mod inspect;
+
+pub struct GeneratePlugin;
+
+impl Plugin for GeneratePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(inspect::InspectPlugin)
+            .init_resource::<CitySettings>()
+            .init_resource::<GroundSettings>()
+            .init_resource::<GroundQueue>()
+            .add_system(ground::generate_ground_system);
+    }
+}
+
+/// Small random number generator (SplitMix64).  It is written out here
+/// instead of using a crate so generated terrain only ever depends on the
+/// seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `range`, which must not be empty
+    pub fn range(&mut self, range: RangeInclusive<i32>) -> i32 {
+        let (lo, hi) = range.into_inner();
+        let len = (i64::from(hi) - i64::from(lo) + 1) as u64;
+        (i64::from(lo) + (self.next_u64() % len) as i64) as i32
+    }
+
+    /// `true` about once every `n` times
+    pub fn one_in(&mut self, n: i32) -> bool {
+        self.range(1..=n) == 1
+    }
+
+    /// One of `items`, which must not be empty
+    pub fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[(self.next_u64() % items.len() as u64) as usize]
+    }
+}
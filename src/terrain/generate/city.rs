@@ -0,0 +1,269 @@
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+use itertools::iproduct;
+
+use crate::terrain::{
+    tile::{color::IndexedColor, shape::Shape, Tile},
+    GlobalPos, Terrain,
+};
+
+use super::Rng;
+
+const STREET_WIDTH: i32 = 3;
+/// Narrowest a lot is split to, leaving room for the sidewalk around its
+/// building
+const MIN_LOT_WIDTH: i32 = 5;
+/// Height of each floor of a building, with a row of windows in the middle
+const FLOOR_HEIGHT: i32 = 3;
+
+/// How a city is generated: a grid of blocks separated by streets, with each
+/// block split into lots holding a building each
+#[derive(Debug, Clone, PartialEq, Eq, Resource)]
+pub struct CitySettings {
+    pub seed: u64,
+    /// Number of blocks along each side of the city
+    pub blocks: i32,
+    /// Width of each block in tiles
+    pub block_size: i32,
+    pub min_height: i32,
+    pub max_height: i32,
+}
+
+impl Default for CitySettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            blocks: 4,
+            block_size: 16,
+            min_height: 4,
+            max_height: 24,
+        }
+    }
+}
+
+impl CitySettings {
+    /// Width of the whole city, including the streets around it
+    pub fn width(&self) -> i32 {
+        self.blocks * (self.block_size + STREET_WIDTH) + STREET_WIDTH
+    }
+
+    /// Adds the city to `terrain`, spreading from the origin towards +x and
+    /// +z with the streets at y 0.  Buildings are colored with `colors`,
+    /// which must not be empty
+    pub fn generate(&self, terrain: &mut Terrain, colors: &[IndexedColor]) {
+        let mut rng = Rng::new(self.seed);
+        let width = self.width();
+        terrain.fill_box(
+            at(IVec2::ZERO, 0),
+            at(IVec2::splat(width - 1), 0),
+            Tile::CONCRETE,
+        );
+        for (x, z) in iproduct!(0..self.blocks, 0..self.blocks) {
+            let start = IVec2::new(x, z) * (self.block_size + STREET_WIDTH) + STREET_WIDTH;
+            let end = start + self.block_size - 1;
+            // Blocks are raised a tile above the streets, like sidewalks
+            terrain.fill_box(at(start, 1), at(end, 1), Tile::CONCRETE);
+            for (start, end) in lots(&mut rng, start, end) {
+                self.add_building(terrain, &mut rng, colors, start + 1, end - 1);
+            }
+        }
+    }
+
+    /// Adds a building standing on the block with its corners at `start` and
+    /// `end`
+    fn add_building(
+        &self,
+        terrain: &mut Terrain,
+        rng: &mut Rng,
+        colors: &[IndexedColor],
+        start: IVec2,
+        end: IVec2,
+    ) {
+        let mut wall = rng.pick(&[Tile::BRICK, Tile::CONCRETE]);
+        *wall.color_mut() = rng.pick(colors);
+        let top = 1 + rng.range(self.min_height..=self.max_height.max(self.min_height));
+        terrain.fill_box(at(start, 2), at(end, top), wall);
+
+        // Windows in the middle of each floor, on every other tile along the
+        // walls starting next to the corners
+        for y in (2 + FLOOR_HEIGHT / 2..top).step_by(FLOOR_HEIGHT as usize) {
+            for x in (start.x + 1..end.x).step_by(2) {
+                terrain.set(at(IVec2::new(x, start.y), y), Tile::GLASS);
+                terrain.set(at(IVec2::new(x, end.y), y), Tile::GLASS);
+            }
+            for z in (start.y + 1..end.y).step_by(2) {
+                terrain.set(at(IVec2::new(start.x, z), y), Tile::GLASS);
+                terrain.set(at(IVec2::new(end.x, z), y), Tile::GLASS);
+            }
+        }
+
+        let mut roof = Tile::CONCRETE;
+        *roof.color_mut() = rng.pick(colors);
+        if !rng.one_in(3) {
+            terrain.fill_box(at(start, top), at(end, top), roof);
+            return;
+        }
+        // A pitched roof, with slopes rising from the front and back to a
+        // ridge along x
+        let ridge = roof;
+        *roof.shape_mut() = Shape::Slope;
+        let back = roof.rotated(IVec3::Y).rotated(IVec3::Y);
+        for i in 0.. {
+            let (back_z, front_z) = (start.y + i, end.y - i);
+            let y = top + 1 + i;
+            let mut fill_rows = |a: i32, b: i32, tile: Tile| {
+                terrain.fill_box(
+                    at(IVec2::new(start.x, a), y),
+                    at(IVec2::new(end.x, b), y),
+                    tile,
+                );
+            };
+            match back_z.cmp(&front_z) {
+                Ordering::Greater => break,
+                Ordering::Equal => {
+                    fill_rows(back_z, back_z, ridge);
+                    break;
+                }
+                Ordering::Less => {
+                    if back_z + 1 < front_z {
+                        fill_rows(back_z + 1, front_z - 1, wall);
+                    }
+                    fill_rows(front_z, front_z, roof);
+                    fill_rows(back_z, back_z, back);
+                }
+            }
+        }
+    }
+}
+
+/// Position `y` tiles up at `xz`
+fn at(xz: IVec2, y: i32) -> GlobalPos {
+    GlobalPos::from_xyz_i32([xz.x, y, xz.y])
+}
+
+/// Splits the block between `start` and `end` into lots, cutting each side
+/// in two at a random point if it is long enough
+fn lots(rng: &mut Rng, start: IVec2, end: IVec2) -> Vec<(IVec2, IVec2)> {
+    let mut split = |lo: i32, hi: i32| {
+        if hi - lo + 1 < 2 * MIN_LOT_WIDTH || rng.one_in(4) {
+            return vec![(lo, hi)];
+        }
+        let at = rng.range(lo + MIN_LOT_WIDTH..=hi + 1 - MIN_LOT_WIDTH);
+        vec![(lo, at - 1), (at, hi)]
+    };
+    let xs = split(start.x, end.x);
+    let zs = split(start.y, end.y);
+    iproduct!(xs, zs)
+        .map(|((x0, x1), (z0, z1))| (IVec2::new(x0, z0), IVec2::new(x1, z1)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use itertools::iproduct;
+
+    use crate::terrain::{
+        chunk::LocalPos,
+        tile::{color::IndexedColor, Tile},
+        GlobalPos, Terrain,
+    };
+
+    use super::CitySettings;
+
+    fn generate(settings: &CitySettings) -> Terrain {
+        let colors = (0..8).map(IndexedColor::new).collect::<Vec<_>>();
+        let mut terrain = Terrain::default();
+        settings.generate(&mut terrain, &colors);
+        terrain
+    }
+
+    /// FNV-1a hash of every tile, visiting chunks in order so the hash does
+    /// not depend on how they are stored
+    fn hash_chunks(terrain: &Terrain) -> u64 {
+        let mut positions = terrain.chunks.keys().copied().collect::<Vec<_>>();
+        positions.sort_by_key(|pos| pos.to_array());
+        let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+        let mut add = |bytes: &[u8]| {
+            for &byte in bytes {
+                hash = (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        for pos in positions {
+            for v in pos.to_array() {
+                add(&v.to_le_bytes());
+            }
+            for local in LocalPos::positions() {
+                match terrain.chunks[&pos][local] {
+                    Some(tile) => add(&[
+                        Tile::KINDS
+                            .iter()
+                            .position(|kind| kind.same_kind(tile))
+                            .unwrap() as u8
+                            + 1,
+                        tile.color().index(),
+                        tile.orientation().index(),
+                        tile.shape().index(),
+                    ]),
+                    None => add(&[0]),
+                }
+            }
+        }
+        hash
+    }
+
+    #[test]
+    fn same_seed_same_city() {
+        let settings = CitySettings {
+            blocks: 2,
+            ..default()
+        };
+        let hash = hash_chunks(&generate(&settings));
+        assert_eq!(hash_chunks(&generate(&settings)), hash);
+        // Changes whenever the generator does, so it is only ever changed on
+        // purpose
+        assert_eq!(hash, 0x48dd_7a43_161c_be1b);
+
+        let other = CitySettings {
+            seed: 1,
+            ..settings
+        };
+        assert_ne!(hash_chunks(&generate(&other)), hash);
+    }
+
+    #[test]
+    fn buildings_within_heights() {
+        let settings = CitySettings {
+            blocks: 3,
+            min_height: 5,
+            max_height: 9,
+            ..default()
+        };
+        let terrain = generate(&settings);
+        let width = settings.width();
+        // Ground, sidewalk, building and a pitched roof at most half as high
+        // as the block is wide
+        let roof = settings.block_size / 2;
+        for (x, z) in iproduct!(0..width, 0..width) {
+            let column = |y: i32| terrain.get(GlobalPos::from_xyz_i32([x, y, z]));
+            assert!(column(0).is_some());
+            assert!(column(2 + settings.max_height + roof).is_none());
+        }
+        // The first block has a building at least as tall as the lowest height
+        let start = IVec2::splat(3);
+        let tallest = (2..20)
+            .filter(|&y| {
+                iproduct!(start.x..start.x + 16, start.y..start.y + 16)
+                    .any(|(x, z)| terrain.get(GlobalPos::from_xyz_i32([x, y, z])).is_some())
+            })
+            .max()
+            .unwrap();
+        assert!(tallest > settings.min_height);
+        assert_eq!(terrain.get(GlobalPos::from_xyz_i32([1, 1, 1])), None);
+        assert_eq!(
+            terrain.get(GlobalPos::from_xyz_i32([3, 1, 3])),
+            Some(Tile::CONCRETE)
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::{cmp::Ordering, mem};
+
+use bevy::prelude::*;
+use itertools::iproduct;
+
+use crate::terrain::{
+    chunk::{ChunkPos, CHUNK_WIDTH},
+    history::History,
+    tile::Tile,
+    GlobalPos, Terrain,
+};
+
+use super::Rng;
+
+/// Distance in tiles between the points the first octave of noise is
+/// sampled at.  Every octave after it is half as wide and half as high
+const BASE_WAVELENGTH: f32 = 64.0;
+const CHUNKS_PER_FRAME: usize = 8;
+const WIDTH: i32 = CHUNK_WIDTH as i32;
+
+/// How natural ground is generated: hills of foliage over concrete, with
+/// water filling everything below sea level
+#[derive(Debug, Clone, PartialEq, Eq, Resource)]
+pub struct GroundSettings {
+    pub seed: u64,
+    /// Number of layers of noise added together, each adding finer detail
+    pub octaves: u32,
+    /// Furthest in tiles the ground rises above or sinks below sea level
+    pub amplitude: i32,
+    pub sea_level: i32,
+    /// Corners of the box of chunks to fill (inclusive)
+    pub min: ChunkPos,
+    pub max: ChunkPos,
+}
+
+impl Default for GroundSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            octaves: 4,
+            amplitude: 16,
+            sea_level: 0,
+            min: ChunkPos::new(-4, -2, -4),
+            max: ChunkPos::new(3, 1, 3),
+        }
+    }
+}
+
+impl GroundSettings {
+    /// Corners of the box of chunks to fill, sorted
+    fn bounds(&self) -> (ChunkPos, ChunkPos) {
+        (self.min.min(self.max), self.min.max(self.max))
+    }
+
+    /// Every chunk in the box between `min` and `max`, one column of chunks
+    /// after another
+    pub fn chunks(&self) -> impl Iterator<Item = ChunkPos> {
+        let (min, max) = self.bounds();
+        iproduct!(min.x..=max.x, min.z..=max.z, min.y..=max.y)
+            .map(|(x, z, y)| ChunkPos::new(x, y, z))
+    }
+
+    /// Height of the top of the ground at `x`, `z`.  Only depends on the
+    /// settings and the position, so chunks can be generated in any order
+    pub fn height(&self, x: i32, z: i32) -> i32 {
+        let mut height = 0.0;
+        let mut total_scale = 0.0;
+        for octave in 0..self.octaves.max(1) {
+            let scale = 0.5_f32.powi(octave as i32);
+            let wavelength = BASE_WAVELENGTH * scale;
+            let seed = self.seed.wrapping_add(u64::from(octave));
+            height += noise(seed, x as f32 / wavelength, z as f32 / wavelength) * scale;
+            total_scale += scale;
+        }
+        self.sea_level + (height / total_scale * self.amplitude as f32).round() as i32
+    }
+
+    /// Height of the ground at every tile in the column of chunks at `column`,
+    /// which is the same for every chunk in it
+    pub fn column_heights(&self, column: IVec2) -> Vec<i32> {
+        let origin = column * WIDTH;
+        iproduct!(0..WIDTH, 0..WIDTH)
+            .map(|(x, z)| self.height(origin.x + x, origin.y + z))
+            .collect()
+    }
+
+    /// Fills the chunk at `pos` with ground, where `heights` are the
+    /// `column_heights` of its column.  Tiles above the ground and water are
+    /// left as they were
+    pub fn generate_chunk(&self, terrain: &mut Terrain, pos: ChunkPos, heights: &[i32]) {
+        let lo = pos * WIDTH;
+        terrain.edit_box_at(
+            GlobalPos::from_xyz_i32(lo),
+            GlobalPos::from_xyz_i32(lo + WIDTH - 1),
+            true,
+            |pos, slot| {
+                let [x, y, z] = pos.xyz().map(|v| v as i32);
+                let height = heights[((x - lo.x) * WIDTH + z - lo.z) as usize];
+                match y.cmp(&height) {
+                    Ordering::Less => Some(Tile::CONCRETE),
+                    // Foliage only grows above water
+                    Ordering::Equal if height >= self.sea_level => Some(Tile::FOLIAGE),
+                    Ordering::Equal => Some(Tile::CONCRETE),
+                    Ordering::Greater if y <= self.sea_level => Some(Tile::WATER),
+                    Ordering::Greater => slot,
+                }
+            },
+        );
+    }
+}
+
+/// Chunks of ground waiting to be generated.  A few are generated each frame
+/// so large areas do not stall the app
+#[derive(Debug, Default, Resource)]
+pub struct GroundQueue {
+    settings: GroundSettings,
+    chunks: Vec<ChunkPos>,
+    /// Heights of the column of chunks being generated, which are reused for
+    /// every chunk in it as they are queued one column after another
+    heights: Option<(IVec2, Vec<i32>)>,
+    /// Whether some of the ground has been generated, so the rest is undone
+    /// along with it
+    started: bool,
+}
+
+impl GroundQueue {
+    /// Replaces the chunks waiting to be generated with every chunk in
+    /// `settings`
+    pub fn start(&mut self, settings: GroundSettings) {
+        self.chunks = settings.chunks().collect();
+        self.settings = settings;
+        self.heights = None;
+        self.started = false;
+    }
+
+    /// Number of chunks still waiting to be generated
+    pub fn remaining(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
+pub fn generate_ground_system(
+    mut terrain: ResMut<Terrain>,
+    mut history: ResMut<History>,
+    mut queue: ResMut<GroundQueue>,
+) {
+    if queue.chunks.is_empty() {
+        return;
+    }
+    let queue = &mut *queue;
+    let continues = mem::replace(&mut queue.started, true);
+    history.record_continued(&mut terrain, "Generate Ground", continues, |terrain| {
+        for _ in 0..CHUNKS_PER_FRAME {
+            let Some(pos) = queue.chunks.pop() else { break };
+            let column = IVec2::new(pos.x, pos.z);
+            let heights = match &queue.heights {
+                Some((cached, heights)) if *cached == column => heights,
+                _ => {
+                    let heights = queue.settings.column_heights(column);
+                    &queue.heights.insert((column, heights)).1
+                }
+            };
+            queue.settings.generate_chunk(terrain, pos, heights);
+        }
+    });
+    if queue.chunks.is_empty() {
+        queue.heights = None;
+    }
+}
+
+/// Smooth value noise between -1 and 1, with random heights at every whole
+/// `x` and `z` blended between
+fn noise(seed: u64, x: f32, z: f32) -> f32 {
+    let (x0, z0) = (x.floor(), z.floor());
+    // Smoothstep, so slopes do not change suddenly between points
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (tx, tz) = (smooth(x - x0), smooth(z - z0));
+    let point = |dx: i32, dz: i32| lattice(seed, x0 as i32 + dx, z0 as i32 + dz);
+    let near = point(0, 0) + (point(1, 0) - point(0, 0)) * tx;
+    let far = point(0, 1) + (point(1, 1) - point(0, 1)) * tx;
+    near + (far - near) * tz
+}
+
+/// Random height between -1 and 1 of the point at `x`, `z`
+fn lattice(seed: u64, x: i32, z: i32) -> f32 {
+    let xz = (u64::from(x as u32) << 32) | u64::from(z as u32);
+    let mut rng = Rng::new(Rng::new(seed).next_u64() ^ xz);
+    (rng.next_u64() >> 40) as f32 / (1 << 23) as f32 - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use itertools::iproduct;
+    use proptest::prelude::*;
+
+    use crate::terrain::{chunk::ChunkPos, tile::Tile, GlobalPos, Terrain};
+
+    use super::GroundSettings;
+
+    fn small_area(seed: u64) -> GroundSettings {
+        GroundSettings {
+            seed,
+            // Keeps every column's ground and water inside the chunks
+            amplitude: 8,
+            min: ChunkPos::new(-1, -1, -1),
+            max: ChunkPos::new(1, 0, 0),
+            ..default()
+        }
+    }
+
+    fn generate(settings: &GroundSettings, chunks: impl Iterator<Item = ChunkPos>) -> Terrain {
+        let mut terrain = Terrain::default();
+        for pos in chunks {
+            let heights = settings.column_heights(IVec2::new(pos.x, pos.z));
+            settings.generate_chunk(&mut terrain, pos, &heights);
+        }
+        terrain
+    }
+
+    #[test]
+    fn generation_order_does_not_matter() {
+        let settings = small_area(7);
+        let forward = generate(&settings, settings.chunks());
+        let chunks = settings.chunks().collect::<Vec<_>>();
+        let backward = generate(&settings, chunks.into_iter().rev());
+        assert_eq!(forward.chunks, backward.chunks);
+        assert!(!forward.chunks.is_empty());
+
+        // Chunks are the same when generated as part of a larger area
+        let larger = GroundSettings {
+            min: ChunkPos::new(-2, -2, -2),
+            max: ChunkPos::new(2, 1, 1),
+            ..settings.clone()
+        };
+        let larger = generate(&larger, larger.chunks());
+        for pos in settings.chunks() {
+            assert_eq!(larger.chunks.get(&pos), forward.chunks.get(&pos));
+        }
+
+        let other = small_area(8);
+        assert_ne!(generate(&other, other.chunks()).chunks, forward.chunks);
+    }
+
+    #[test]
+    fn water_fills_below_sea_level() {
+        let settings = small_area(3);
+        let terrain = generate(&settings, settings.chunks());
+        for (x, z) in iproduct!(-16..32, -16..16) {
+            let height = settings.height(x, z);
+            let tile = |y: i32| terrain.get(GlobalPos::from_xyz_i32([x, y, z]));
+            assert_eq!(tile(height - 1), Some(Tile::CONCRETE));
+            for y in height + 1..=settings.sea_level {
+                assert_eq!(tile(y), Some(Tile::WATER));
+            }
+            assert_eq!(tile(height.max(settings.sea_level) + 1), None);
+        }
+
+        // Chunks above the ground and water are left empty
+        let sky = GroundSettings {
+            min: ChunkPos::new(-1, 2, -1),
+            max: ChunkPos::new(1, 2, 0),
+            ..settings
+        };
+        assert!(generate(&sky, sky.chunks()).chunks.is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn heights_within_amplitude(
+            seed: u64,
+            octaves in 1..6_u32,
+            amplitude in 0..64,
+            sea_level in -64..64,
+            x: i32,
+            z: i32,
+        ) {
+            let settings = GroundSettings { seed, octaves, amplitude, sea_level, ..default() };
+            let height = settings.height(x, z);
+            prop_assert!((height - sea_level).abs() <= amplitude);
+            prop_assert_eq!(settings.height(x, z), height);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+
+use crate::{
+    menu,
+    terrain::{
+        history::History,
+        palette::{Palette, Palettes},
+        Terrain,
+    },
+};
+
+use super::{
+    city::CitySettings,
+    ground::{GroundQueue, GroundSettings},
+};
+
+pub struct InspectPlugin;
+
+impl Plugin for InspectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(inspect_generate_system)
+            .add_system(inspect_ground_system);
+    }
+}
+
+fn inspect_generate_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut terrain: ResMut<Terrain>,
+    mut history: ResMut<History>,
+    mut settings: ResMut<CitySettings>,
+    palettes: Res<Palettes>,
+    palette_assets: Res<Assets<Palette>>,
+) {
+    let palette = palette_assets.get(&palettes.current);
+    egui::Window::new("City Generator")
+        .open(&mut true)
+        .default_width(200.0)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            let CitySettings {
+                seed,
+                blocks,
+                block_size,
+                min_height,
+                max_height,
+            } = &mut *settings;
+            egui::Grid::new("City Settings").show(ui, |ui| {
+                ui.label("Seed");
+                ui.add(egui::DragValue::new(seed));
+                ui.end_row();
+
+                ui.label("Blocks");
+                ui.add(egui::DragValue::new(blocks).clamp_range(1..=16));
+                ui.end_row();
+
+                ui.label("Block Size");
+                ui.add(egui::DragValue::new(block_size).clamp_range(5..=64));
+                ui.end_row();
+
+                ui.label("Min Height");
+                ui.add(egui::DragValue::new(min_height).clamp_range(1..=*max_height));
+                ui.end_row();
+
+                ui.label("Max Height");
+                ui.add(egui::DragValue::new(max_height).clamp_range(*min_height..=128));
+                ui.end_row();
+            });
+
+            ui.vertical_centered_justified(|ui| {
+                let generate = ui
+                    .add_enabled(palette.is_some(), egui::Button::new("Generate"))
+                    .on_hover_text("Replace the terrain with a new city")
+                    .on_disabled_hover_text("Waiting for the palette to load");
+                if let Some(palette) = palette.filter(|_| generate.clicked()) {
+                    let colors = palette.colors().collect::<Vec<_>>();
+                    history.record(&mut terrain, "Generate City", |terrain| {
+                        terrain.clear();
+                        settings.generate(terrain, &colors);
+                    });
+                }
+            });
+        });
+}
+
+fn inspect_ground_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut settings: ResMut<GroundSettings>,
+    mut queue: ResMut<GroundQueue>,
+) {
+    egui::Window::new("Ground Generator")
+        .open(&mut true)
+        .default_width(200.0)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            let GroundSettings {
+                seed,
+                octaves,
+                amplitude,
+                sea_level,
+                min,
+                max,
+            } = &mut *settings;
+            egui::Grid::new("Ground Settings").show(ui, |ui| {
+                ui.label("Seed");
+                ui.add(egui::DragValue::new(seed));
+                ui.end_row();
+
+                ui.label("Octaves");
+                ui.add(egui::DragValue::new(octaves).clamp_range(1..=6));
+                ui.end_row();
+
+                ui.label("Amplitude");
+                ui.add(egui::DragValue::new(amplitude).clamp_range(0..=128));
+                ui.end_row();
+
+                ui.label("Sea Level");
+                ui.add(egui::DragValue::new(sea_level));
+                ui.end_row();
+
+                for (label, chunk) in [("From Chunk", min), ("To Chunk", max)] {
+                    ui.label(label);
+                    let mut xyz = chunk.to_array();
+                    if ui.add(menu::edit_slice(&mut xyz)).changed() {
+                        *chunk = xyz.into();
+                    }
+                    ui.end_row();
+                }
+            });
+
+            ui.vertical_centered_justified(|ui| {
+                let generate = ui
+                    .button("Generate")
+                    .on_hover_text("Add ground to every chunk in the box, a few chunks each frame");
+                if generate.clicked() {
+                    queue.start(settings.clone());
+                }
+                if queue.remaining() > 0 {
+                    ui.label(format!("{} chunks left", queue.remaining()));
+                }
+            });
+        });
+}
@@ -0,0 +1,312 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+
+use super::{chunk::TileSlot, GlobalPos, Terrain};
+
+/// Most edit batches kept before the oldest are forgotten
+const MAX_HISTORY: usize = 256;
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<History>()
+            .add_system(history_shortcut_system)
+            .add_system(history_panel_system);
+    }
+}
+
+/// Undo and redo stacks of terrain edits
+#[derive(Debug, Default, Resource)]
+pub struct History {
+    undo: Vec<EditBatch>,
+    redo: Vec<EditBatch>,
+}
+
+/// Every tile changed by a single action
+#[derive(Debug)]
+struct EditBatch {
+    name: String,
+    edits: Vec<Edit>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edit {
+    pos: GlobalPos,
+    before: TileSlot,
+    after: TileSlot,
+}
+
+impl Edit {
+    /// Returns `None` if nothing changed
+    pub fn new(pos: GlobalPos, before: TileSlot, after: TileSlot) -> Option<Self> {
+        (before != after).then_some(Self { pos, before, after })
+    }
+}
+
+impl History {
+    /// Records every change `edit` makes to `terrain` as a single undoable
+    /// batch
+    pub fn record<R>(
+        &mut self,
+        terrain: &mut Terrain,
+        name: impl Into<String>,
+        edit: impl FnOnce(&mut Terrain) -> R,
+    ) -> R {
+        self.record_continued(terrain, name, false, edit)
+    }
+
+    /// Like `record`, but if `continues` is set and the latest batch has the
+    /// same name and has not been undone, the changes are added to it instead.
+    /// Used for actions that edit terrain over several frames, like dragging
+    /// a slider
+    pub fn record_continued<R>(
+        &mut self,
+        terrain: &mut Terrain,
+        name: impl Into<String>,
+        continues: bool,
+        edit: impl FnOnce(&mut Terrain) -> R,
+    ) -> R {
+        assert!(terrain.recording.is_none(), "Edits should not be nested");
+        terrain.recording = Some(Vec::new());
+        let ret = edit(terrain);
+        let mut edits = terrain.recording.take().unwrap();
+
+        if !edits.is_empty() {
+            let name = name.into();
+            match self.undo.last_mut() {
+                Some(batch) if continues && self.redo.is_empty() && batch.name == name => {
+                    batch.edits.append(&mut edits);
+                }
+                _ => {
+                    self.redo.clear();
+                    self.undo.push(EditBatch { name, edits });
+                    if self.undo.len() > MAX_HISTORY {
+                        self.undo.remove(0);
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    /// Reverts the latest batch, returning `false` if there is nothing to undo
+    pub fn undo(&mut self, terrain: &mut Terrain) -> bool {
+        let Some(batch) = self.undo.pop() else { return false };
+        for edit in batch.edits.iter().rev() {
+            terrain.set_slot(edit.pos, edit.before);
+        }
+        self.redo.push(batch);
+        true
+    }
+
+    /// Reapplies the latest undone batch, returning `false` if there is
+    /// nothing to redo
+    pub fn redo(&mut self, terrain: &mut Terrain) -> bool {
+        let Some(batch) = self.redo.pop() else { return false };
+        for edit in &batch.edits {
+            terrain.set_slot(edit.pos, edit.after);
+        }
+        self.undo.push(batch);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+fn history_shortcut_system(
+    mut egui_context: ResMut<EguiContext>,
+    keys: Res<Input<KeyCode>>,
+    mut history: ResMut<History>,
+    mut terrain: ResMut<Terrain>,
+) {
+    if egui_context.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    if !ctrl {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Y) || (shift && keys.just_pressed(KeyCode::Z)) {
+        history.redo(&mut terrain);
+    } else if keys.just_pressed(KeyCode::Z) {
+        history.undo(&mut terrain);
+    }
+}
+
+fn history_panel_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut history: ResMut<History>,
+    mut terrain: ResMut<Terrain>,
+) {
+    egui::Window::new("History")
+        .open(&mut true)
+        .default_width(200.0)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.columns(2, |columns| {
+                let undo =
+                    columns[0].add_enabled(!history.undo.is_empty(), egui::Button::new("Undo"));
+                if undo.on_hover_text("Ctrl+Z").clicked() {
+                    history.undo(&mut terrain);
+                }
+                let redo =
+                    columns[1].add_enabled(!history.redo.is_empty(), egui::Button::new("Redo"));
+                if redo.on_hover_text("Ctrl+Y").clicked() {
+                    history.redo(&mut terrain);
+                }
+            });
+            ui.separator();
+
+            // Clicking an entry undoes or redoes until it is the latest
+            // applied batch
+            let mut target = None;
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    ui.vertical_centered_justified(|ui| {
+                        let undo_len = history.undo.len();
+                        for (i, batch) in history.undo.iter().enumerate() {
+                            let label = ui.selectable_label(i + 1 == undo_len, batch.label());
+                            if label.clicked() {
+                                target = Some(i + 1);
+                            }
+                        }
+                        for (i, batch) in history.redo.iter().rev().enumerate() {
+                            let text = egui::RichText::new(batch.label()).weak();
+                            if ui.selectable_label(false, text).clicked() {
+                                target = Some(undo_len + i + 1);
+                            }
+                        }
+                    });
+                });
+            if let Some(target) = target {
+                while history.undo.len() > target && history.undo(&mut terrain) {}
+                while history.undo.len() < target && history.redo(&mut terrain) {}
+            }
+
+            ui.separator();
+            ui.vertical_centered_justified(|ui| {
+                if ui.button("Clear History").clicked() {
+                    history.clear();
+                }
+            });
+        });
+}
+
+impl EditBatch {
+    fn label(&self) -> String {
+        format!("{} ({})", self.name, self.edits.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::terrain::{tests::arb_global_pos, tile::Tile, Terrain};
+
+    use super::History;
+
+    proptest! {
+        #[test]
+        fn undo_redo_terrain(
+            before in proptest::collection::vec(arb_global_pos(), 0..64),
+            edits in proptest::collection::vec(
+                (arb_global_pos(), proptest::bool::ANY),
+                1..64,
+            ),
+        ) {
+            let mut terrain = Terrain::default();
+            for &pos in &before {
+                terrain.set(pos, Tile::BRICK);
+            }
+            let original = terrain.chunks.keys().copied().collect::<Vec<_>>();
+            let mut history = History::default();
+
+            history.record(&mut terrain, "Edit", |terrain| {
+                for &(pos, set) in &edits {
+                    match set {
+                        true => terrain.set(pos, Tile::BRICK),
+                        false => terrain.remove(pos),
+                    }
+                }
+            });
+            let edited = before
+                .iter()
+                .chain(edits.iter().map(|(pos, _)| pos))
+                .map(|&pos| (pos, terrain.get(pos)))
+                .collect::<Vec<_>>();
+
+            terrain.changed.clear();
+            history.undo(&mut terrain);
+            for &pos in &before {
+                assert_eq!(terrain.get(pos), Some(Tile::BRICK));
+            }
+            for &(pos, slot) in &edited {
+                assert_eq!(terrain.get(pos).is_some(), before.contains(&pos));
+                if slot != terrain.get(pos) {
+                    assert!(terrain.changed.contains(&pos.chunk));
+                }
+            }
+            assert_eq!(terrain.chunks.len(), original.len());
+
+            history.redo(&mut terrain);
+            for (pos, slot) in edited {
+                assert_eq!(terrain.get(pos), slot);
+            }
+        }
+
+        #[test]
+        fn continued_edits_undo_together(
+            poses in proptest::collection::vec(arb_global_pos(), 1..64),
+        ) {
+            let mut terrain = Terrain::default();
+            let mut history = History::default();
+            for (i, &pos) in poses.iter().enumerate() {
+                history.record_continued(&mut terrain, "Drag", i > 0, |terrain| {
+                    terrain.set(pos, Tile::BRICK)
+                });
+            }
+            prop_assert_eq!(history.undo.len(), 1);
+
+            // Starting over makes a new batch
+            history.record_continued(&mut terrain, "Drag", false, |terrain| {
+                terrain.remove(poses[0])
+            });
+            prop_assert_eq!(history.undo.len(), 2);
+
+            history.undo(&mut terrain);
+            history.undo(&mut terrain);
+            prop_assert!(terrain.chunks.is_empty());
+        }
+
+        #[test]
+        fn undo_clear(poses in proptest::collection::vec(arb_global_pos(), 1..64)) {
+            let mut terrain = Terrain::default();
+            let mut history = History::default();
+            history.record(&mut terrain, "Fill", |terrain| {
+                for &pos in &poses {
+                    terrain.set(pos, Tile::BRICK);
+                }
+            });
+
+            history.record(&mut terrain, "Clear", Terrain::clear);
+            assert!(terrain.chunks.is_empty());
+
+            history.undo(&mut terrain);
+            for &pos in &poses {
+                assert_eq!(terrain.get(pos), Some(Tile::BRICK));
+            }
+
+            history.undo(&mut terrain);
+            assert!(terrain.chunks.is_empty());
+            assert!(!history.undo(&mut terrain));
+        }
+    }
+}
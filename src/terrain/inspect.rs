@@ -3,29 +3,93 @@ use bevy_egui::{egui, EguiContext};
 
 use crate::menu;
 
-use super::{tile::Tile, GlobalPos, Terrain};
+use super::{history::History, palette::Swatches, tile::Tile, GlobalPos, Terrain};
+
+mod gizmo;
 
 pub struct InspectPlugin;
 
 impl Plugin for InspectPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(inspect_tile_system);
+        app.add_plugin(gizmo::GizmoPlugin)
+            .init_resource::<InspectedTile>()
+            .add_system(inspect_tile_system);
     }
 }
 
+/// The tile being edited in the tile inspector
+#[derive(Debug, Default, Resource)]
+pub struct InspectedTile {
+    pub pos: GlobalPos,
+    /// Whether to outline the chunk containing `pos`
+    pub show_chunk: bool,
+}
+
 fn inspect_tile_system(
     mut egui_context: ResMut<EguiContext>,
     mut terrain: ResMut<Terrain>,
-    mut pos: Local<GlobalPos>,
+    mut history: ResMut<History>,
+    mut inspected: ResMut<InspectedTile>,
+    mut swatches: ResMut<Swatches>,
+    mut corner: Local<GlobalPos>,
+    mut editing: Local<bool>,
 ) {
+    let InspectedTile { pos, show_chunk } = &mut *inspected;
     egui::Window::new("Tile Inspector")
         .open(&mut true)
         .default_width(200.0)
         .resizable(false)
         .show(egui_context.ctx_mut(), |ui| {
             ui.add(&mut *pos);
+            ui.checkbox(show_chunk, "Show Chunk");
             ui.separator();
-            ui.add(terrain.widget_edit_tile(*pos));
+            // Dragging or typing into a value edits the tile every frame, but
+            // is undone as one action
+            let response = history.record_continued(
+                &mut terrain,
+                format!("Edit {}", *pos),
+                *editing,
+                |terrain| ui.add(terrain.widget_edit_tile(*pos, &mut swatches)),
+            );
+            *editing = response.dragged() || ui.memory().focus().is_some();
+            ui.separator();
+            ui.collapsing("Box", |ui| {
+                ui.add(&mut *corner);
+                let tile = terrain.get(*pos);
+                ui.columns(3, |columns| {
+                    let fill = columns[0]
+                        .button("Fill")
+                        .on_hover_text("Fill the box with the inspected tile");
+                    if fill.clicked() {
+                        history.record(&mut terrain, "Fill Box", |terrain| match tile {
+                            Some(tile) => terrain.fill_box(*pos, *corner, tile),
+                            None => terrain.clear_box(*pos, *corner),
+                        });
+                    }
+                    let replace = columns[1]
+                        .button("Replace")
+                        .on_hover_text("Replace tiles matching the corner with the inspected tile");
+                    if replace.clicked() {
+                        let from = terrain.get(*corner);
+                        history.record(&mut terrain, "Replace Box", |terrain| {
+                            terrain.replace_in_box(*pos, *corner, from, tile)
+                        });
+                    }
+                    if columns[2].button("Clear").clicked() {
+                        history.record(&mut terrain, "Clear Box", |terrain| {
+                            terrain.clear_box(*pos, *corner)
+                        });
+                    }
+                });
+                let turn = ui
+                    .button("Turn")
+                    .on_hover_text("Turn every tile in the box a quarter turn around y");
+                if turn.clicked() {
+                    history.record(&mut terrain, "Turn Box", |terrain| {
+                        terrain.turn_in_box(*pos, *corner, IVec3::Y)
+                    });
+                }
+            });
         });
 }
 
@@ -47,11 +111,15 @@ impl egui::Widget for &mut GlobalPos {
 }
 
 impl Terrain {
-    pub fn widget_edit_tile(&mut self, pos: GlobalPos) -> impl egui::Widget + '_ {
+    pub fn widget_edit_tile<'a>(
+        &'a mut self,
+        pos: GlobalPos,
+        swatches: &'a mut Swatches,
+    ) -> impl egui::Widget + 'a {
         move |ui: &mut egui::Ui| {
             ui.vertical_centered_justified(|ui| {
                 let mut slot = self.get(pos);
-                let response = ui.add(Tile::widget_option(&mut slot));
+                let response = ui.add(Tile::widget_option(&mut slot, swatches));
                 if response.changed() {
                     self.set_slot(pos, slot);
                 }
@@ -0,0 +1,101 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+
+use crate::terrain::chunk::CHUNK_WIDTH;
+
+use super::InspectedTile;
+
+pub struct GizmoPlugin;
+
+impl Plugin for GizmoPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(spawn_gizmos_system)
+            .add_system(update_gizmos_system);
+    }
+}
+
+/// Outline around the inspected tile
+#[derive(Debug, Component)]
+struct TileCursor;
+
+/// Outline around the chunk containing the inspected tile
+#[derive(Debug, Component)]
+struct ChunkBounds;
+
+fn spawn_gizmos_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let cube = meshes.add(outline_cube_mesh());
+    let mut outline = |color: Color| {
+        materials.add(StandardMaterial {
+            base_color: color,
+            unlit: true,
+            ..default()
+        })
+    };
+
+    commands.spawn((
+        PbrBundle {
+            mesh: cube.clone(),
+            material: outline(Color::YELLOW),
+            ..default()
+        },
+        TileCursor,
+    ));
+    commands.spawn((
+        PbrBundle {
+            mesh: cube,
+            material: outline(Color::CYAN),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        },
+        ChunkBounds,
+    ));
+}
+
+fn update_gizmos_system(
+    inspected: Res<InspectedTile>,
+    mut cursors: Query<&mut Transform, (With<TileCursor>, Without<ChunkBounds>)>,
+    mut chunk_bounds: Query<(&mut Transform, &mut Visibility), With<ChunkBounds>>,
+) {
+    // Slightly larger than a tile so the outline isn't hidden by its faces
+    for mut transform in &mut cursors {
+        *transform =
+            Transform::from_translation(inspected.pos.center()).with_scale(Vec3::splat(1.02));
+    }
+
+    let width = CHUNK_WIDTH as f32;
+    let chunk_center = inspected.pos.chunk.as_vec3() * width + Vec3::splat(width / 2.0 - 1.0);
+    for (mut transform, mut visibility) in &mut chunk_bounds {
+        *transform = Transform::from_translation(chunk_center).with_scale(Vec3::splat(width));
+        visibility.is_visible = inspected.show_chunk;
+    }
+}
+
+/// The edges of a unit cube centered on the origin
+fn outline_cube_mesh() -> Mesh {
+    let positions = (0..8)
+        .map(|i| [i & 0b100, i & 0b010, i & 0b001].map(|v| if v == 0 { -0.5 } else { 0.5 }))
+        .collect::<Vec<[f32; 3]>>();
+    let normals = positions
+        .iter()
+        .map(|&p| Vec3::from(p).normalize().to_array())
+        .collect::<Vec<_>>();
+    // Every pair of corners that differ along exactly one axis
+    let indices = (0..8_u32)
+        .flat_map(|i| [0b100, 0b010, 0b001].map(|axis| [i, i | axis]))
+        .filter(|[a, b]| a != b)
+        .flatten()
+        .collect();
+
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; 8]);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
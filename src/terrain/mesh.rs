@@ -1,24 +1,44 @@
-use std::ops::{Index, IndexMut};
+use std::{
+    array,
+    ops::{Index, IndexMut},
+};
 
 use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
     prelude::*,
     reflect::TypeUuid,
     render::{
-        primitives::Aabb,
-        render_resource::{AsBindGroup, PrimitiveTopology},
+        mesh::{Indices, MeshVertexBufferLayout},
+        primitives::{Aabb, Frustum},
+        render_resource::{
+            AsBindGroup, PrimitiveTopology, RenderPipelineDescriptor, ShaderType,
+            SpecializedMeshPipelineError,
+        },
     },
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::{FloatOrd, HashMap, HashSet},
 };
+use bevy_flycam::FlyCam;
 use bitflags::bitflags;
+use futures_lite::future;
+use itertools::iproduct;
 
-use self::mesh_builder::{MeshBuilder, SubtileFace};
+use self::{
+    culling::ChunkConnections,
+    lighting::{LightingUniform, TerrainLighting},
+    mesh_builder::{MeshBuilder, MeshLayer, SubtileFace, ATTRIBUTE_OCCLUSION},
+};
 
 use super::{
     chunk::{Chunk, ChunkPos, LocalPos, TileSlot, CHUNK_WIDTH},
-    tile::Tile,
-    Terrain,
+    palette::{Palette, Palettes},
+    tile::{orientation::Orientation, Tile},
+    GlobalPos, Terrain,
 };
 
+pub mod culling;
 mod inspect;
+pub mod lighting;
 pub mod mesh_builder;
 
 pub struct MeshPlugin;
@@ -26,486 +46,745 @@ pub struct MeshPlugin;
 impl Plugin for MeshPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(inspect::InspectPlugin)
+            .add_plugin(lighting::LightingPlugin)
+            .add_plugin(culling::CullingPlugin)
+            .init_resource::<MeshSettings>()
+            .init_resource::<MeshBudget>()
+            .init_resource::<LodSettings>()
+            .init_resource::<MeshTasks>()
             .add_startup_system(init_material_system)
             .add_plugin(MaterialPlugin::<OpaqueTerrainMaterial>::default())
-            .add_system(generate_meshes_system);
+            .add_plugin(MaterialPlugin::<TransparentTerrainMaterial>::default())
+            .add_system(generate_meshes_system)
+            .add_system(update_palette_system);
+    }
+}
+
+/// How chunk meshes are built
+#[derive(Debug, Resource)]
+pub struct MeshSettings {
+    /// Whether to merge quads in the same plane with the same color
+    pub merge_quads: bool,
+}
+
+impl Default for MeshSettings {
+    fn default() -> Self {
+        Self { merge_quads: true }
+    }
+}
+
+/// How much meshing is started each frame, so large edits are spread over
+/// several frames instead of freezing the app
+#[derive(Debug, Resource)]
+pub struct MeshBudget {
+    pub chunks_per_frame: usize,
+}
+
+impl Default for MeshBudget {
+    fn default() -> Self {
+        Self {
+            chunks_per_frame: 32,
+        }
+    }
+}
+
+/// How far from the camera chunks are meshed at lower levels of detail
+#[derive(Debug, Resource)]
+pub struct LodSettings {
+    pub enabled: bool,
+    /// Distance in tiles where chunks switch to the first coarser level, with
+    /// each level after it starting twice as far away
+    pub distance: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            distance: 128.0,
+        }
     }
 }
 
 fn init_material_system(
     mut commands: Commands,
-    mut materials: ResMut<Assets<OpaqueTerrainMaterial>>,
-    asset_server: Res<AssetServer>,
+    mut opaque_materials: ResMut<Assets<OpaqueTerrainMaterial>>,
+    mut transparent_materials: ResMut<Assets<TransparentTerrainMaterial>>,
+    lighting: Res<TerrainLighting>,
 ) {
+    // The palette is set by `update_palette_system` once it has loaded
+    let lighting = LightingUniform::from(&*lighting);
     commands.insert_resource(TerrainMaterialHandles {
-        opaque: materials.add(OpaqueTerrainMaterial {
-            // TODO: remove hardcoded palette
-            texture: asset_server.load("palettes/test.png"),
+        opaque: opaque_materials.add(OpaqueTerrainMaterial {
+            layout: default(),
+            texture: default(),
+            lighting,
+        }),
+        transparent: transparent_materials.add(TransparentTerrainMaterial {
+            layout: default(),
+            texture: default(),
+            opacity: 0.5,
+            lighting,
         }),
     });
 }
 
+/// Points the materials at the current palette whenever it is picked or
+/// reloaded
+fn update_palette_system(
+    palettes: Res<Palettes>,
+    palette_assets: Res<Assets<Palette>>,
+    mut palette_events: EventReader<AssetEvent<Palette>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    materials: Res<TerrainMaterialHandles>,
+    mut opaque_materials: ResMut<Assets<OpaqueTerrainMaterial>>,
+    mut transparent_materials: ResMut<Assets<TransparentTerrainMaterial>>,
+) {
+    let palette_changed = palette_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            *handle == palettes.current
+        }
+        AssetEvent::Removed { .. } => false,
+    });
+    let Some(palette) = palette_assets.get(&palettes.current) else { return };
+    // Materials are not rebuilt when only the image changes
+    let image_changed = image_events.iter().any(|event| match event {
+        AssetEvent::Modified { handle } => *handle == palette.texture,
+        _ => false,
+    });
+    if !(palettes.is_changed() || palette_changed || image_changed) {
+        return;
+    }
+
+    let layout = PaletteLayout::new(palette);
+    let opaque = opaque_materials.get_mut(&materials.opaque).unwrap();
+    opaque.layout = layout;
+    opaque.texture = palette.texture.clone();
+    let transparent = transparent_materials
+        .get_mut(&materials.transparent)
+        .unwrap();
+    transparent.layout = layout;
+    transparent.texture = palette.texture.clone();
+}
+
+/// Where the colors are in the palette image
+#[derive(Debug, Default, Clone, Copy, ShaderType)]
+struct PaletteLayout {
+    columns: u32,
+    len: u32,
+}
+
+impl PaletteLayout {
+    fn new(palette: &Palette) -> Self {
+        Self {
+            columns: palette.columns.into(),
+            len: palette.len.into(),
+        }
+    }
+}
+
 #[derive(Debug, AsBindGroup, TypeUuid, Clone)]
 #[uuid = "d8ec3dfe-1da4-418b-93dc-b99a0fe0ee1c"]
 struct OpaqueTerrainMaterial {
+    #[uniform(0)]
+    layout: PaletteLayout,
     #[texture(1)]
-    #[sampler(2)]
     texture: Handle<Image>,
+    #[uniform(2)]
+    lighting: LightingUniform,
 }
 
 impl Material for OpaqueTerrainMaterial {
+    fn vertex_shader() -> bevy::render::render_resource::ShaderRef {
+        "terrain/vertex_shader.wgsl".into()
+    }
+
     fn fragment_shader() -> bevy::render::render_resource::ShaderRef {
         "terrain/opaque_shader.wgsl".into()
     }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // Adds occlusion to the attributes the vertex shader is given
+        descriptor.vertex.buffers = vec![layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
+            ATTRIBUTE_OCCLUSION.at_shader_location(3),
+        ])?];
+        Ok(())
+    }
+}
+
+#[derive(Debug, AsBindGroup, TypeUuid, Clone)]
+#[uuid = "5d0c4a3e-7b5f-4f0e-9a51-3c1f6c0b8e27"]
+struct TransparentTerrainMaterial {
+    #[uniform(0)]
+    layout: PaletteLayout,
+    #[texture(1)]
+    texture: Handle<Image>,
+    /// Multiplied with the alpha of the palette
+    #[uniform(2)]
+    opacity: f32,
+    #[uniform(3)]
+    lighting: LightingUniform,
+}
+
+impl Material for TransparentTerrainMaterial {
+    fn fragment_shader() -> bevy::render::render_resource::ShaderRef {
+        "terrain/transparent_shader.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
 }
 
 #[derive(Debug, Clone, Resource)]
 struct TerrainMaterialHandles {
     opaque: Handle<OpaqueTerrainMaterial>,
+    transparent: Handle<TransparentTerrainMaterial>,
+}
+
+/// Entity and mesh of each layer of a chunk
+#[derive(Debug, Clone)]
+pub struct ChunkMeshes {
+    layers: [(Entity, Handle<Mesh>); 2],
+    /// Level of detail of the latest meshes started for the chunk
+    lod: Lod,
+    /// Sides of the chunk that can be seen from each other through it, as of
+    /// the meshes shown for it
+    connections: ChunkConnections,
+}
+
+impl ChunkMeshes {
+    /// Shows the meshes built for each layer
+    fn replace(
+        &self,
+        built: [Mesh; 2],
+        transform: Transform,
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+    ) {
+        for (mesh, (entity, mesh_handle)) in built.into_iter().zip(&self.layers) {
+            commands
+                .entity(*entity)
+                .insert((transform, mesh.compute_aabb().unwrap_or(Default::default())));
+            *meshes.get_mut(mesh_handle).unwrap() = mesh;
+        }
+    }
+
+    fn despawn(self, commands: &mut Commands) {
+        for (entity, _) in self.layers {
+            commands.entity(entity).despawn();
+        }
+    }
 }
 
+/// Size of the meshes of every chunk
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MeshStats {
+    pub vertices: usize,
+    pub indices: usize,
+}
+
+impl MeshStats {
+    /// Bytes used by each vertex, for its position, normal, uv and occlusion
+    const VERTEX_SIZE: usize = std::mem::size_of::<[f32; 9]>();
+    const INDEX_SIZE: usize = std::mem::size_of::<u32>();
+
+    pub fn new(terrain: &Terrain, meshes: &Assets<Mesh>) -> Self {
+        let mut stats = Self::default();
+        for chunk_meshes in terrain.mesh_ids.values() {
+            for (_, mesh_handle) in &chunk_meshes.layers {
+                let Some(mesh) = meshes.get(mesh_handle) else { continue };
+                stats.vertices += mesh.count_vertices();
+                stats.indices += mesh.indices().map_or(0, Indices::len);
+            }
+        }
+        stats
+    }
+
+    pub fn bytes(self) -> usize {
+        self.vertices * Self::VERTEX_SIZE + self.indices * Self::INDEX_SIZE
+    }
+
+    /// Bytes the meshes would use without indices, with a vertex for every
+    /// index
+    pub fn unindexed_bytes(self) -> usize {
+        self.indices * Self::VERTEX_SIZE
+    }
+}
+
+impl Index<MeshLayer> for ChunkMeshes {
+    type Output = (Entity, Handle<Mesh>);
+
+    fn index(&self, index: MeshLayer) -> &Self::Output {
+        &self.layers[index as usize]
+    }
+}
+
+/// Resolution a chunk is meshed at, where each level halves the number of
+/// tiles along each axis
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Lod(u8);
+
+impl Lod {
+    pub const FULL: Self = Self(0);
+    /// The coarsest level, where each tile is made of 4x4x4 tiles
+    pub const MAX: Self = Self(2);
+
+    /// The level of a chunk `distance` tiles from the camera
+    fn new(distance: f32, settings: &LodSettings) -> Self {
+        let mut lod = Self::FULL;
+        if settings.enabled {
+            while lod != Self::MAX && distance >= settings.distance * lod.scale() as f32 {
+                lod.0 += 1;
+            }
+        }
+        lod
+    }
+
+    /// How many tiles wide each tile is at this level
+    fn scale(self) -> i32 {
+        1 << self.0
+    }
+
+    /// Transform of the meshes of the chunk at `pos` at this level
+    fn transform(self, pos: ChunkPos) -> Transform {
+        let scale = self.scale() as f32;
+        // Coarse tiles are made of the tiles after their far corner, so are
+        // moved to where the last of them ends
+        let translation = pos.as_vec3() * CHUNK_WIDTH as f32 + (scale - 1.0);
+        Transform::from_translation(translation).with_scale(Vec3::splat(scale))
+    }
+}
+
+/// Chunks around a chunk that its mesh is closed against, with a bit for each
+/// of them.  These are the chunks at a different level of detail, so no gaps
+/// are left between the mesh and the coarser or finer meshes next to it, or
+/// every chunk around a coarse chunk
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Seams(u32);
+
+impl Seams {
+    /// Seams of a chunk at `lod`, where `neighbour` gets the level of the chunk
+    /// `offset` from it
+    fn new(lod: Lod, neighbour: impl Fn(IVec3) -> Lod) -> Self {
+        let mut seams = Self::default();
+        for (x, y, z) in iproduct!(-1..=1, -1..=1, -1..=1) {
+            let offset = IVec3::new(x, y, z);
+            if offset != IVec3::ZERO && (lod != Lod::FULL || neighbour(offset) != lod) {
+                seams.0 |= 1 << neighbour_index(offset);
+            }
+        }
+        seams
+    }
+
+    /// Whether the mesh is closed against the chunk `offset` from it
+    fn contains(self, offset: IVec3) -> bool {
+        self.0 & 1 << neighbour_index(offset) != 0
+    }
+
+    /// Whether the mesh is closed against any chunk before it, which is when
+    /// corners before it can be meshed with it
+    fn any_before(self) -> bool {
+        iproduct!(-1..=0, -1..=0, -1..=0).any(|(x, y, z)| self.contains(IVec3::new(x, y, z)))
+    }
+
+    /// Whether a chunk at full detail meshes the corner at `pos`, which is on
+    /// its border or a tile before it.  The tiles around corners like these
+    /// are in more than one chunk, and each corner is meshed by the first of
+    /// those chunks that is not behind a seam, so it is meshed once
+    fn meshes_corner(self, pos: IVec3) -> bool {
+        let last = IVec3::splat(i32::from(CHUNK_WIDTH) - 1);
+        let border = pos.cmplt(IVec3::ZERO) | pos.cmpeq(last);
+        // Where this chunk is among the chunks around the corner
+        let this = IVec3::select(pos.cmplt(IVec3::ZERO), IVec3::ONE, IVec3::ZERO);
+        iproduct!(0..=1, 0..=1, 0..=1)
+            .map(|(x, y, z)| IVec3::new(x, y, z))
+            .filter(|&chunk| (chunk.cmpeq(IVec3::ZERO) | border).all())
+            .take_while(|&chunk| chunk != this)
+            .all(|chunk| self.contains(chunk - this))
+    }
+}
+
+/// Index of the chunk `offset` from another among the 27 chunks around and
+/// including it
+fn neighbour_index(offset: IVec3) -> usize {
+    (offset + 1).dot(IVec3::new(9, 3, 1)) as usize
+}
+
+/// Offset of the chunk holding the tile at `pos` relative to the start of a
+/// chunk, from -1 to 1 on each axis
+fn chunk_offset(pos: IVec3) -> IVec3 {
+    let width = IVec3::splat(CHUNK_WIDTH.into());
+    let after = IVec3::select(pos.cmpge(width), IVec3::ONE, IVec3::ZERO);
+    IVec3::select(pos.cmplt(IVec3::ZERO), IVec3::NEG_ONE, after)
+}
+
+/// Meshes being built off the main thread, and the level of detail they are
+/// built at
+#[derive(Default, Resource)]
+struct MeshTasks(HashMap<ChunkPos, (Lod, Task<BuiltChunk>)>);
+
+/// Meshes of each layer of a chunk, and the connections through it
+type BuiltChunk = ([Mesh; 2], ChunkConnections);
+
+#[allow(clippy::too_many_arguments)]
 fn generate_meshes_system(
     mut commands: Commands,
     mut terrain: ResMut<Terrain>,
     mut meshes: ResMut<Assets<Mesh>>,
     materials: Res<TerrainMaterialHandles>,
-    empty_chunk: Local<Chunk>,
+    settings: Res<MeshSettings>,
+    budget: Res<MeshBudget>,
+    lod_settings: Res<LodSettings>,
+    mut tasks: ResMut<MeshTasks>,
+    camera: Query<(&GlobalTransform, &Frustum), With<FlyCam>>,
 ) {
     let terrain = &mut *terrain;
-    for chunk_pos in terrain.changed.drain() {
-        if let Some(chunk) = terrain.chunks.get(&chunk_pos) {
-            let (entity, mesh_handle) = &terrain.mesh_ids.entry(chunk_pos).or_insert_with(|| {
-                init_chunk_mesh(&mut commands, &mut meshes, &materials, chunk_pos)
-            });
-            let mesh = meshes.get_mut(mesh_handle).unwrap();
-            let mut mesh_builder = MeshBuilder::edit(mesh);
-
-            add_inner_tiles(chunk, &mut mesh_builder);
-
-            let x_face_chunk = terrain
-                .chunks
-                .get(&(chunk_pos + ChunkPos::X))
-                .unwrap_or(&empty_chunk);
-            add_x_face_tiles(chunk, x_face_chunk, &mut mesh_builder);
-            let y_face_chunk = terrain
-                .chunks
-                .get(&(chunk_pos + ChunkPos::Y))
-                .unwrap_or(&empty_chunk);
-            add_y_face_tiles(chunk, y_face_chunk, &mut mesh_builder);
-            let z_face_chunk = terrain
-                .chunks
-                .get(&(chunk_pos + ChunkPos::Z))
-                .unwrap_or(&empty_chunk);
-            add_z_face_tiles(chunk, z_face_chunk, &mut mesh_builder);
-
-            let xy_edge_chunk = &terrain
-                .chunks
-                .get(&(chunk_pos + ChunkPos::X + ChunkPos::Y))
-                .unwrap_or(&empty_chunk);
-            add_edge_tiles(&mut mesh_builder, |z| {
-                let pos = LocalPos::new([15, 15, z]).unwrap();
-                let tiles = CornerTiles([
-                    chunk[pos],
-                    chunk[pos.inc_z()],
-                    y_face_chunk[pos & LocalPos::XZ_MASK],
-                    y_face_chunk[pos.inc_z() & LocalPos::XZ_MASK],
-                    x_face_chunk[pos & LocalPos::YZ_MASK],
-                    x_face_chunk[pos.inc_z() & LocalPos::YZ_MASK],
-                    xy_edge_chunk[pos & LocalPos::Z_MASK],
-                    xy_edge_chunk[pos.inc_z() & LocalPos::Z_MASK],
-                ]);
-                (tiles, pos)
-            });
+    if settings.is_changed() {
+        terrain.changed.extend(terrain.mesh_ids.keys());
+    }
+    let camera = camera
+        .get_single()
+        .ok()
+        .map(|(transform, frustum)| (transform.translation(), frustum));
+    let lod_at = |pos| match camera {
+        Some((eye, _)) => Lod::new(distance_to_chunk(eye, pos), &lod_settings),
+        None => Lod::FULL,
+    };
+    for (&pos, chunk_meshes) in &terrain.mesh_ids {
+        if chunk_meshes.lod != lod_at(pos) {
+            // The seams of the chunks around it change too
+            let around = iproduct!(-1..=1, -1..=1, -1..=1).map(|(x, y, z)| IVec3::new(x, y, z));
+            terrain.changed.extend(around.map(|offset| pos + offset));
+        }
+    }
 
-            let xz_edge_chunk = &terrain
-                .chunks
-                .get(&(chunk_pos + ChunkPos::X + ChunkPos::Z))
-                .unwrap_or(&empty_chunk);
-            add_edge_tiles(&mut mesh_builder, |y| {
-                let pos = LocalPos::new([15, y, 15]).unwrap();
-                let tiles = CornerTiles([
-                    chunk[pos],
-                    z_face_chunk[pos & LocalPos::XY_MASK],
-                    chunk[pos.inc_y()],
-                    z_face_chunk[pos.inc_y() & LocalPos::XY_MASK],
-                    x_face_chunk[pos & LocalPos::YZ_MASK],
-                    xz_edge_chunk[pos & LocalPos::Y_MASK],
-                    x_face_chunk[pos.inc_y() & LocalPos::YZ_MASK],
-                    xz_edge_chunk[pos.inc_y() & LocalPos::Y_MASK],
-                ]);
-                (tiles, pos)
-            });
+    let task_pool = AsyncComputeTaskPool::get();
+    for chunk_pos in take_changed(&mut terrain.changed, camera, budget.chunks_per_frame) {
+        let lod = lod_at(chunk_pos);
+        let seams = Seams::new(lod, |offset| lod_at(chunk_pos + offset));
+        let neighbours = ChunkNeighbours::new(&terrain.chunks, chunk_pos, seams);
+        if neighbours.is_empty() {
+            tasks.0.remove(&chunk_pos);
+            if let Some(chunk_meshes) = terrain.mesh_ids.remove(&chunk_pos) {
+                chunk_meshes.despawn(&mut commands);
+            }
+            continue;
+        }
+        // Added before the meshes are built so clearing the terrain clears
+        // them too
+        let chunk_meshes = terrain.mesh_ids.entry(chunk_pos).or_insert_with(|| {
+            init_chunk_meshes(&mut commands, &mut meshes, &materials, chunk_pos)
+        });
+        chunk_meshes.lod = lod;
+        let merge_quads = settings.merge_quads;
+        // Replacing the task of an older version of the chunk cancels it, so
+        // its meshes are never shown
+        let task = task_pool.spawn(async move {
+            let connections = neighbours
+                .get(ChunkPos::ZERO)
+                .map_or(ChunkConnections::OPEN, ChunkConnections::new);
+            let built = neighbours.build_meshes(merge_quads, lod, seams);
+            (built, connections)
+        });
+        tasks.0.insert(chunk_pos, (lod, task));
+    }
 
-            let yz_edge_chunk = &terrain
-                .chunks
-                .get(&(chunk_pos + ChunkPos::Y + ChunkPos::Z))
-                .unwrap_or(&empty_chunk);
-            add_edge_tiles(&mut mesh_builder, |x| {
-                let pos = LocalPos::new([x, 15, 15]).unwrap();
-                let tiles = CornerTiles([
-                    chunk[pos],
-                    z_face_chunk[pos & LocalPos::XY_MASK],
-                    y_face_chunk[pos & LocalPos::XZ_MASK],
-                    yz_edge_chunk[pos & LocalPos::X_MASK],
-                    chunk[pos.inc_x()],
-                    z_face_chunk[pos.inc_x() & LocalPos::XY_MASK],
-                    y_face_chunk[pos.inc_x() & LocalPos::XZ_MASK],
-                    yz_edge_chunk[pos.inc_x() & LocalPos::X_MASK],
-                ]);
-                (tiles, pos)
+    tasks.0.retain(|chunk_pos, (lod, task)| {
+        let Some(done) = future::block_on(future::poll_once(task)) else { return true };
+        if let Some(chunk_meshes) = terrain.mesh_ids.get_mut(chunk_pos) {
+            let (built, connections) = done;
+            let transform = lod.transform(*chunk_pos);
+            chunk_meshes.replace(built, transform, &mut commands, &mut meshes);
+            chunk_meshes.connections = connections;
+        }
+        false
+    });
+}
+
+/// Removes up to `budget` chunks from `changed`, starting with the ones the
+/// camera can see and then the nearest
+fn take_changed(
+    changed: &mut HashSet<ChunkPos>,
+    camera: Option<(Vec3, &Frustum)>,
+    budget: usize,
+) -> Vec<ChunkPos> {
+    let mut queue = Vec::from_iter(changed.iter().copied());
+    if queue.len() > budget {
+        if let Some((eye, frustum)) = camera {
+            queue.sort_by_cached_key(|&pos| {
+                let (min, max) = chunk_bounds(pos);
+                let aabb = Aabb::from_min_max(min, max);
+                let visible = frustum.intersects_obb(&aabb, &Mat4::IDENTITY, true);
+                // Chunks as far away are taken in order of position, so the
+                // order does not depend on the order of `changed`
+                let distance = FloatOrd(distance_to_chunk(eye, pos));
+                (!visible, distance, pos.to_array())
             });
+        }
+        queue.truncate(budget);
+    }
+    for pos in &queue {
+        changed.remove(pos);
+    }
+    queue
+}
 
-            generate_corner_mesh(
-                CornerTiles([
-                    chunk[LocalPos::try_from_bits(0xfff).unwrap()],
-                    z_face_chunk[LocalPos::try_from_bits(0xff0).unwrap()],
-                    y_face_chunk[LocalPos::try_from_bits(0xf0f).unwrap()],
-                    yz_edge_chunk[LocalPos::try_from_bits(0xf00).unwrap()],
-                    x_face_chunk[LocalPos::try_from_bits(0x0ff).unwrap()],
-                    xz_edge_chunk[LocalPos::try_from_bits(0x0f0).unwrap()],
-                    xy_edge_chunk[LocalPos::try_from_bits(0x00f).unwrap()],
-                    terrain
-                        .chunks
-                        .get(&(chunk_pos + ChunkPos::ONE))
-                        .map(|c| c[LocalPos::ZERO])
-                        .unwrap_or(None),
-                ]),
-                LocalPos::try_from_bits(0xfff).unwrap(),
-                &mut mesh_builder,
-            );
+/// Corners of the box around the chunk at `pos`.  The tile at `pos` fills
+/// the cube from `pos - 1` to `pos`, so chunks start a tile below their first
+/// position
+fn chunk_bounds(pos: ChunkPos) -> (Vec3, Vec3) {
+    let min = pos.as_vec3() * CHUNK_WIDTH as f32 - 1.0;
+    (min, min + CHUNK_WIDTH as f32)
+}
 
-            commands
-                .entity(*entity)
-                .insert(mesh.compute_aabb().unwrap_or(Default::default()));
-        } else {
-            let set = [
-                [0, 0, 1],
-                [0, 1, 0],
-                [0, 1, 1],
-                [1, 0, 0],
-                [1, 0, 1],
-                [1, 1, 0],
-                [1, 1, 1],
-            ]
-            .map(|pos| terrain.chunks.contains_key(&(chunk_pos + IVec3::from(pos))));
-            #[allow(clippy::eq_op)]
-            if set.into_iter().any(|v| v) {
-                let (entity, mesh_handle) =
-                    &terrain.mesh_ids.entry(chunk_pos).or_insert_with(|| {
-                        init_chunk_mesh(&mut commands, &mut meshes, &materials, chunk_pos)
-                    });
-                let mesh = &mut meshes.get_mut(mesh_handle).unwrap();
-                let mut mesh_builder = MeshBuilder::edit(mesh);
-
-                let x_face_chunk = terrain
-                    .chunks
-                    .get(&(chunk_pos + ChunkPos::X))
-                    .unwrap_or(&empty_chunk);
-                if set[0b100 - 1] {
-                    add_x_face_tiles(&empty_chunk, x_face_chunk, &mut mesh_builder);
-                }
-                let y_face_chunk = terrain
-                    .chunks
-                    .get(&(chunk_pos + ChunkPos::Y))
-                    .unwrap_or(&empty_chunk);
-                if set[0b010 - 1] {
-                    add_y_face_tiles(&empty_chunk, y_face_chunk, &mut mesh_builder);
-                }
-                let z_face_chunk = terrain
-                    .chunks
-                    .get(&(chunk_pos + ChunkPos::Z))
-                    .unwrap_or(&empty_chunk);
-                if set[0b001 - 1] {
-                    add_z_face_tiles(&empty_chunk, z_face_chunk, &mut mesh_builder);
-                }
+/// Distance from `eye` to the nearest point of the chunk at `pos`
+fn distance_to_chunk(eye: Vec3, pos: ChunkPos) -> f32 {
+    let (min, max) = chunk_bounds(pos);
+    eye.distance(eye.clamp(min, max))
+}
 
-                let xy_edge_chunk = &terrain
-                    .chunks
-                    .get(&(chunk_pos + ChunkPos::X + ChunkPos::Y))
-                    .unwrap_or(&empty_chunk);
-                if set[0b100 - 1] || set[0b010 - 1] || set[0b110 - 1] {
-                    add_edge_tiles(&mut mesh_builder, |z| {
-                        let pos = LocalPos::new([15, 15, z]).unwrap();
-                        let tiles = CornerTiles([
-                            None,
-                            None,
-                            y_face_chunk[pos & LocalPos::XZ_MASK],
-                            y_face_chunk[pos.inc_z() & LocalPos::XZ_MASK],
-                            x_face_chunk[pos & LocalPos::YZ_MASK],
-                            x_face_chunk[pos.inc_z() & LocalPos::YZ_MASK],
-                            xy_edge_chunk[pos & LocalPos::Z_MASK],
-                            xy_edge_chunk[pos.inc_z() & LocalPos::Z_MASK],
-                        ]);
-                        (tiles, pos)
-                    });
-                }
+/// Tiles that can be looked up by position, to find the tiles around corners
+/// anywhere in them
+trait SampleTiles {
+    fn tile(&self, pos: IVec3) -> TileSlot;
 
-                let xz_edge_chunk = &terrain
-                    .chunks
-                    .get(&(chunk_pos + ChunkPos::X + ChunkPos::Z))
-                    .unwrap_or(&empty_chunk);
-                if set[0b100 - 1] || set[0b001 - 1] || set[0b101 - 1] {
-                    add_edge_tiles(&mut mesh_builder, |y| {
-                        let pos = LocalPos::new([15, y, 15]).unwrap();
-                        let tiles = CornerTiles([
-                            None,
-                            z_face_chunk[pos & LocalPos::XY_MASK],
-                            None,
-                            z_face_chunk[pos.inc_y() & LocalPos::XY_MASK],
-                            x_face_chunk[pos & LocalPos::YZ_MASK],
-                            xz_edge_chunk[pos & LocalPos::Y_MASK],
-                            x_face_chunk[pos.inc_y() & LocalPos::YZ_MASK],
-                            xz_edge_chunk[pos.inc_y() & LocalPos::Y_MASK],
-                        ]);
-                        (tiles, pos)
-                    });
-                }
+    /// The tiles around the corner at `pos`, which is the far corner of the
+    /// tile at `pos`
+    fn corner_tiles(&self, pos: IVec3) -> CornerTiles {
+        CornerTiles(array::from_fn(|i| {
+            let offset = IVec3::new(i as i32 >> 2, i as i32 >> 1, i as i32) & 1;
+            self.tile(pos + offset)
+        }))
+    }
+}
 
-                let yz_edge_chunk = &terrain
-                    .chunks
-                    .get(&(chunk_pos + ChunkPos::Y + ChunkPos::Z))
-                    .unwrap_or(&empty_chunk);
-                if set[0b010 - 1] || set[0b001 - 1] || set[0b011 - 1] {
-                    add_edge_tiles(&mut mesh_builder, |x| {
-                        let pos = LocalPos::new([x, 15, 15]).unwrap();
-                        let tiles = CornerTiles([
-                            None,
-                            z_face_chunk[pos & LocalPos::XY_MASK],
-                            y_face_chunk[pos & LocalPos::XZ_MASK],
-                            yz_edge_chunk[pos & LocalPos::X_MASK],
-                            None,
-                            z_face_chunk[pos.inc_x() & LocalPos::XY_MASK],
-                            y_face_chunk[pos.inc_x() & LocalPos::XZ_MASK],
-                            yz_edge_chunk[pos.inc_x() & LocalPos::X_MASK],
-                        ]);
-                        (tiles, pos)
-                    });
-                }
+impl SampleTiles for Terrain {
+    fn tile(&self, pos: IVec3) -> TileSlot {
+        self.get(GlobalPos::from_xyz_i32(pos))
+    }
+}
 
-                generate_corner_mesh(
-                    CornerTiles([
-                        None,
-                        z_face_chunk[LocalPos::try_from_bits(0xff0).unwrap()],
-                        y_face_chunk[LocalPos::try_from_bits(0xf0f).unwrap()],
-                        yz_edge_chunk[LocalPos::try_from_bits(0xf00).unwrap()],
-                        x_face_chunk[LocalPos::try_from_bits(0x0ff).unwrap()],
-                        xz_edge_chunk[LocalPos::try_from_bits(0x0f0).unwrap()],
-                        xy_edge_chunk[LocalPos::try_from_bits(0x00f).unwrap()],
-                        terrain
-                            .chunks
-                            .get(&(chunk_pos + ChunkPos::ONE))
-                            .map(|c| c[LocalPos::ZERO])
-                            .unwrap_or(None),
-                    ]),
-                    LocalPos::try_from_bits(0xfff).unwrap(),
-                    &mut mesh_builder,
-                );
+/// Copies of a chunk and the chunks around it, which is everything needed to
+/// mesh it
+struct ChunkNeighbours([Option<Chunk>; 27]);
 
-                commands
-                    .entity(*entity)
-                    .insert(mesh.compute_aabb().unwrap_or(Default::default()));
-            } else if let Some((entity, _)) = terrain.mesh_ids.remove(&chunk_pos) {
-                commands.entity(entity).despawn();
+impl ChunkNeighbours {
+    /// Copies the chunk at `pos` and the chunks after it along each axis, which
+    /// hold the tiles around its corners.  Chunks behind `seams` are left out,
+    /// and the chunks before it are only copied when it meshes corners before
+    /// it
+    fn new(chunks: &HashMap<ChunkPos, Chunk>, pos: ChunkPos, seams: Seams) -> Self {
+        let before = seams.any_before();
+        Self(array::from_fn(|i| {
+            let offset = IVec3::new(i as i32 / 9, i as i32 / 3, i as i32) % 3 - 1;
+            let needed = before || offset.cmpge(IVec3::ZERO).all();
+            match needed && !seams.contains(offset) {
+                true => chunks.get(&(pos + offset)).cloned(),
+                false => None,
+            }
+        }))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(Option::is_none)
+    }
+
+    /// The chunk `offset` from this one, which should be between -1 and 1 on
+    /// each axis
+    fn get(&self, offset: ChunkPos) -> Option<&Chunk> {
+        self.0[neighbour_index(offset)].as_ref()
+    }
+
+    /// Builds the mesh of each layer at `lod`, closed on `seams`
+    fn build_meshes(&self, merge_quads: bool, lod: Lod, seams: Seams) -> [Mesh; 2] {
+        let mut mesh_builder = MeshBuilder::default();
+        mesh_builder.set_merge_quads(merge_quads);
+
+        let width = i32::from(CHUNK_WIDTH);
+        if lod == Lod::FULL {
+            if let Some(chunk) = self.get(ChunkPos::ZERO) {
+                add_inner_tiles(chunk, &mut mesh_builder);
+            }
+            // Corners on the border have tiles in the chunks after this one,
+            // and corners before seams may be meshed here instead of in the
+            // chunks before it
+            let sealed = Sealed { tiles: self, seams };
+            for (x, y, z) in iproduct!(-1..width, -1..width, -1..width) {
+                let pos = IVec3::new(x, y, z);
+                let border = pos.cmpeq(IVec3::splat(width - 1)) | pos.cmplt(IVec3::ZERO);
+                if border.any() && seams.meshes_corner(pos) {
+                    let tiles = sealed.corner_tiles(pos);
+                    generate_corner_mesh(tiles, pos.as_vec3(), &mut mesh_builder);
+                }
+            }
+        } else if let Some(chunk) = self.get(ChunkPos::ZERO) {
+            // Coarse meshes are closed on every side, starting a corner before
+            // the chunk
+            let coarse = Coarse::new(chunk, lod.scale());
+            let width = width / lod.scale();
+            for (x, y, z) in iproduct!(-1..width, -1..width, -1..width) {
+                let pos = IVec3::new(x, y, z);
+                generate_corner_mesh(coarse.corner_tiles(pos), pos.as_vec3(), &mut mesh_builder);
             }
         }
+
+        MeshLayer::LAYERS.map(|layer| {
+            let mut mesh = empty_mesh();
+            mesh_builder.finish(layer, &mut mesh);
+            mesh
+        })
     }
 }
 
-fn add_inner_tiles(chunk: &Chunk, mesh: &mut MeshBuilder) {
-    for pos in LocalPos::inner_positions() {
-        let tiles = CornerTiles([
-            chunk[pos],
-            chunk[pos.inc_z()],
-            chunk[pos.inc_y()],
-            chunk[pos.inc_z().inc_y()],
-            chunk[pos.inc_x()],
-            chunk[pos.inc_x().inc_z()],
-            chunk[pos.inc_x().inc_y()],
-            chunk[pos.inc_x().inc_z().inc_y()],
-        ]);
-        generate_corner_mesh(tiles, pos, mesh);
+impl SampleTiles for ChunkNeighbours {
+    /// The tile at `pos` relative to the start of the chunk, which can be in
+    /// any of the chunks around it
+    fn tile(&self, pos: IVec3) -> TileSlot {
+        let offset = chunk_offset(pos);
+        let chunk = self.get(offset)?;
+        chunk[LocalPos::from_ivec3(pos - offset * i32::from(CHUNK_WIDTH)).unwrap()]
     }
 }
 
-fn add_x_face_tiles(chunk: &Chunk, next_chunk: &Chunk, mesh: &mut MeshBuilder) {
-    add_face_tiles(
-        chunk,
-        next_chunk,
-        mesh,
-        {
-            #[derive(Debug)]
-            pub struct Iter(u16);
+/// Tiles with the ones in chunks behind the seams of a chunk removed
+struct Sealed<'a, S> {
+    tiles: &'a S,
+    seams: Seams,
+}
 
-            impl Iterator for Iter {
-                type Item = LocalPos;
+impl<S: SampleTiles> SampleTiles for Sealed<'_, S> {
+    fn tile(&self, pos: IVec3) -> TileSlot {
+        match self.seams.contains(chunk_offset(pos)) {
+            true => None,
+            false => self.tiles.tile(pos),
+        }
+    }
+}
 
-                fn next(&mut self) -> Option<Self::Item> {
-                    self.0 = self.0.wrapping_add(1);
-                    let mut skip_edge = |edge, add| {
-                        if self.0 & edge == edge {
-                            self.0 += add;
-                        }
-                    };
-                    skip_edge(0x00f, 0x001);
-                    skip_edge(0x0f0, 0x010);
-                    LocalPos::try_from_bits(self.0)
-                }
-            }
+/// Tiles of a chunk at a coarser level of detail, with every tile outside it
+/// empty
+struct Coarse {
+    tiles: Chunk,
+    width: i32,
+}
 
-            Iter(0x0f00 - 1)
-        },
-        |chunk, next_chunk, pos| {
-            let next_pos = LocalPos::try_from_bits(pos.bits() & 0x00ff).unwrap();
-            CornerTiles([
-                chunk[pos],
-                chunk[pos.inc_z()],
-                chunk[pos.inc_y()],
-                chunk[pos.inc_z().inc_y()],
-                next_chunk[next_pos],
-                next_chunk[next_pos.inc_z()],
-                next_chunk[next_pos.inc_y()],
-                next_chunk[next_pos.inc_z().inc_y()],
-            ])
-        },
-    )
-}
-
-fn add_y_face_tiles(chunk: &Chunk, next_chunk: &Chunk, mesh: &mut MeshBuilder) {
-    add_face_tiles(
-        chunk,
-        next_chunk,
-        mesh,
-        {
-            #[derive(Debug)]
-            pub struct Iter(u16);
-
-            impl Iterator for Iter {
-                type Item = LocalPos;
-
-                fn next(&mut self) -> Option<Self::Item> {
-                    self.0 = self.0.wrapping_add(1);
-                    if self.0 & 0x00f == 0x00f {
-                        self.0 += 0x001;
-                    }
-                    if self.0 & 0x0f0 != 0 {
-                        self.0 &= 0xf0f;
-                        self.0 += 0x100;
-                    }
-                    if self.0 & 0xf00 == 0xf00 {
-                        self.0 += 0x100;
-                    }
-                    LocalPos::try_from_bits(self.0 | 0x0f0)
+impl Coarse {
+    /// Replaces each block of `scale` tiles along each axis with the most
+    /// common tile in it, or leaves it empty if less than half of it is filled
+    fn new(chunk: &Chunk, scale: i32) -> Self {
+        let width = i32::from(CHUNK_WIDTH) / scale;
+        let mut tiles = Chunk::default();
+        for (x, y, z) in iproduct!(0..width, 0..width, 0..width) {
+            let start = IVec3::new(x, y, z) * scale;
+            let mut counts = Vec::<(Tile, i32)>::new();
+            for (x, y, z) in iproduct!(0..scale, 0..scale, 0..scale) {
+                let pos = LocalPos::from_ivec3(start + IVec3::new(x, y, z)).unwrap();
+                let Some(tile) = chunk[pos] else { continue };
+                match counts.iter_mut().find(|(counted, _)| *counted == tile) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((tile, 1)),
                 }
             }
-
-            Iter(u16::MAX)
-        },
-        |chunk, next_chunk, pos| {
-            let next_pos = LocalPos::try_from_bits(pos.bits() & 0x0f0f).unwrap();
-            CornerTiles([
-                chunk[pos],
-                chunk[pos.inc_z()],
-                next_chunk[next_pos],
-                next_chunk[next_pos.inc_z()],
-                chunk[pos.inc_x()],
-                chunk[pos.inc_x().inc_z()],
-                next_chunk[next_pos.inc_x()],
-                next_chunk[next_pos.inc_x().inc_z()],
-            ])
-        },
-    )
-}
-
-fn add_z_face_tiles(chunk: &Chunk, next_chunk: &Chunk, mesh: &mut MeshBuilder) {
-    add_face_tiles(
-        chunk,
-        next_chunk,
-        mesh,
-        {
-            #[derive(Debug)]
-            pub struct Iter(u16);
-
-            impl Iterator for Iter {
-                type Item = LocalPos;
-
-                fn next(&mut self) -> Option<Self::Item> {
-                    self.0 = self.0.wrapping_add(0x10);
-                    let mut skip_edge = |edge, add| {
-                        if self.0 & edge == edge {
-                            self.0 += add;
-                        }
-                    };
-                    skip_edge(0x0f0, 0x010);
-                    skip_edge(0xf00, 0x100);
-                    LocalPos::try_from_bits(self.0 | 0x00f)
-                }
+            let filled: i32 = counts.iter().map(|(_, count)| count).sum();
+            if filled * 2 >= scale.pow(3) {
+                let (tile, _) = counts.into_iter().max_by_key(|(_, count)| *count).unwrap();
+                let _ = tiles.set(LocalPos::from_ivec3(IVec3::new(x, y, z)).unwrap(), tile);
             }
-
-            Iter(0xffff)
-        },
-        |chunk, next_chunk, pos| {
-            let next_pos = LocalPos::try_from_bits(pos.bits() & 0x0ff0).unwrap();
-            CornerTiles([
-                chunk[pos],
-                next_chunk[next_pos],
-                chunk[pos.inc_y()],
-                next_chunk[next_pos.inc_y()],
-                chunk[pos.inc_x()],
-                next_chunk[next_pos.inc_x()],
-                chunk[pos.inc_x().inc_y()],
-                next_chunk[next_pos.inc_x().inc_y()],
-            ])
-        },
-    )
+        }
+        Self { tiles, width }
+    }
 }
 
-fn add_face_tiles(
-    chunk: &Chunk,
-    next_chunk: &Chunk,
-    mesh: &mut MeshBuilder,
-    iter: impl Iterator<Item = LocalPos>,
-    extract_tiles: impl Fn(&Chunk, &Chunk, LocalPos) -> CornerTiles,
-) {
-    for pos in iter {
-        generate_corner_mesh(extract_tiles(chunk, next_chunk, pos), pos, mesh);
+impl SampleTiles for Coarse {
+    fn tile(&self, pos: IVec3) -> TileSlot {
+        let inside = pos.cmpge(IVec3::ZERO).all() && pos.cmplt(IVec3::splat(self.width)).all();
+        match inside {
+            true => self.tiles[LocalPos::from_ivec3(pos).unwrap()],
+            false => None,
+        }
     }
 }
 
-fn add_edge_tiles(mesh: &mut MeshBuilder, extract_tiles: impl Fn(u8) -> (CornerTiles, LocalPos)) {
-    for pos in 0..(CHUNK_WIDTH - 1) {
-        let (tiles, pos) = extract_tiles(pos);
-        generate_corner_mesh(tiles, pos, mesh);
+fn add_inner_tiles(chunk: &Chunk, mesh: &mut MeshBuilder) {
+    for pos in LocalPos::inner_positions() {
+        let tiles = CornerTiles([
+            chunk[pos],
+            chunk[pos.inc_z()],
+            chunk[pos.inc_y()],
+            chunk[pos.inc_z().inc_y()],
+            chunk[pos.inc_x()],
+            chunk[pos.inc_x().inc_z()],
+            chunk[pos.inc_x().inc_y()],
+            chunk[pos.inc_x().inc_z().inc_y()],
+        ]);
+        generate_corner_mesh(tiles, pos.to_vec3(), mesh);
     }
 }
 
-fn init_chunk_mesh(
+fn init_chunk_meshes(
     commands: &mut Commands,
     meshes: &mut Assets<Mesh>,
     materials: &TerrainMaterialHandles,
     pos: ChunkPos,
-) -> (Entity, Handle<Mesh>) {
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<[f32; 3]>::new());
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, Vec::<[f32; 3]>::new());
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, Vec::<[f32; 2]>::new());
-    let mesh = meshes.add(mesh);
+) -> ChunkMeshes {
+    let mut init_mesh = || meshes.add(empty_mesh());
+    let transform = Transform::from_translation(pos.as_vec3() * CHUNK_WIDTH as f32);
 
-    let entity = commands
+    let opaque = init_mesh();
+    let opaque_entity = commands
         .spawn((
             MaterialMeshBundle {
-                mesh: mesh.clone(),
+                mesh: opaque.clone(),
                 material: materials.opaque.clone(),
-                transform: Transform::from_translation(pos.as_vec3() * CHUNK_WIDTH as f32),
+                transform,
+                ..default()
+            },
+            Aabb::default(),
+        ))
+        .id();
+    let transparent = init_mesh();
+    let transparent_entity = commands
+        .spawn((
+            MaterialMeshBundle {
+                mesh: transparent.clone(),
+                material: materials.transparent.clone(),
+                transform,
                 ..default()
             },
             Aabb::default(),
         ))
         .id();
-    (entity, mesh)
+    ChunkMeshes {
+        layers: [(opaque_entity, opaque), (transparent_entity, transparent)],
+        lod: Lod::FULL,
+        connections: ChunkConnections::OPEN,
+    }
 }
 
-fn generate_corner_mesh(tiles: CornerTiles, pos: LocalPos, mesh: &mut MeshBuilder) {
-    mesh.set_offset(pos.to_vec3());
+/// Mesh with the attributes `MeshBuilder` expects
+fn empty_mesh() -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<[f32; 3]>::new());
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, Vec::<[f32; 3]>::new());
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, Vec::<[f32; 2]>::new());
+    mesh.insert_attribute(ATTRIBUTE_OCCLUSION, Vec::<f32>::new());
+    mesh.set_indices(Some(Indices::U32(Vec::new())));
+    mesh
+}
+
+/// Meshes the tiles around the corner at `pos`
+fn generate_corner_mesh(tiles: CornerTiles, pos: Vec3, mesh: &mut MeshBuilder) {
+    mesh.set_offset(pos);
+    mesh.set_occluders(tiles.0.map(Tile::is_solid));
     if tiles != CornerTiles([None; 8]) {
         for subtile in Subtile::subtiles() {
             Tile::generate_mesh(&tiles, subtile, mesh);
@@ -524,6 +803,19 @@ impl Index<Subtile> for CornerTiles {
     }
 }
 
+impl CornerTiles {
+    /// The tiles around the corner as a tile turned to `orientation` sees
+    /// them, so it can be meshed as if it were upright
+    pub fn unapply(&self, orientation: Orientation) -> Self {
+        Self(array::from_fn(|i| {
+            let local = Subtile::from_bits_truncate(i as u8);
+            let mut tile = self[Subtile::from_offset(orientation.apply(local.offset()))]?;
+            *tile.orientation_mut() = tile.orientation().relative_to(orientation);
+            Some(tile)
+        }))
+    }
+}
+
 impl IndexMut<Subtile> for CornerTiles {
     fn index_mut(&mut self, index: Subtile) -> &mut Self::Output {
         &mut self.0[index.bits() as usize]
@@ -557,4 +849,790 @@ impl Subtile {
     pub fn tile_at_face(self, face: SubtileFace) -> Self {
         self ^ face.subtile_axis()
     }
+
+    /// Direction along `axis` from the shared corner into the tile of this
+    /// subtile
+    pub fn into_tile(self, axis: Subtile) -> Vec3 {
+        let dir = Vec3::new(
+            axis.contains(Subtile::X) as u8 as f32,
+            axis.contains(Subtile::Y) as u8 as f32,
+            axis.contains(Subtile::Z) as u8 as f32,
+        );
+        match self.contains(axis) {
+            true => dir,
+            false => -dir,
+        }
+    }
+
+    /// Direction from the shared corner to the center of this subtile, with
+    /// every component 1 or -1
+    pub fn offset(self) -> Vec3 {
+        let sign = |axis| if self.contains(axis) { 1.0 } else { -1.0 };
+        Vec3::new(sign(Subtile::X), sign(Subtile::Y), sign(Subtile::Z))
+    }
+
+    /// The subtile `offset` from the shared corner
+    pub fn from_offset(offset: Vec3) -> Self {
+        let mut subtile = Subtile::empty();
+        subtile.set(Subtile::X, offset.x > 0.0);
+        subtile.set(Subtile::Y, offset.y > 0.0);
+        subtile.set(Subtile::Z, offset.z > 0.0);
+        subtile
+    }
+
+    /// The axis `dir` points along
+    pub fn from_axis(dir: Vec3) -> Self {
+        if dir.x != 0.0 {
+            Subtile::X
+        } else if dir.y != 0.0 {
+            Subtile::Y
+        } else {
+            Subtile::Z
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        f32::consts::FRAC_PI_4,
+        f64::consts::{FRAC_1_PI, FRAC_1_SQRT_2},
+    };
+
+    use bevy::{
+        math::DVec2,
+        prelude::*,
+        render::{
+            mesh::{MeshVertexAttributeId, VertexAttributeValues},
+            primitives::Frustum,
+        },
+        utils::{HashMap, HashSet},
+    };
+    use itertools::iproduct;
+    use proptest::prelude::*;
+
+    use crate::terrain::{
+        chunk::{Chunk, ChunkPos, LocalPos, CHUNK_WIDTH},
+        tile::{
+            color::IndexedColor, orientation::tests::arb_orientation, shape::tests::arb_shape,
+            shape::Shape, Tile,
+        },
+        GlobalPos, Terrain,
+    };
+
+    use super::{
+        add_inner_tiles, distance_to_chunk, empty_mesh, generate_corner_mesh,
+        mesh_builder::{MeshBuilder, MeshLayer, ATTRIBUTE_OCCLUSION, EDGE_RADIUS},
+        take_changed, ChunkNeighbours, Coarse, CornerTiles, Lod, LodSettings, SampleTiles, Seams,
+    };
+
+    /// Vertices of every layer of a mesh, three for each triangle
+    #[derive(Debug, Default, PartialEq)]
+    struct Vertices {
+        position: Vec<Vec3>,
+        normal: Vec<Vec3>,
+        uv: Vec<Vec2>,
+        occlusion: Vec<f32>,
+    }
+
+    /// Meshes tiles between 1 and 14 on each axis, so every corner around
+    /// them is an inner corner.  Returns the mesh of each layer
+    fn build_meshes(tiles: &[([u8; 3], Tile)], merge_quads: bool) -> [Mesh; 2] {
+        let mut chunk = Chunk::default();
+        for &(pos, tile) in tiles {
+            let _ = chunk.set(LocalPos::new(pos).unwrap(), tile);
+        }
+        let mut mesh_builder = MeshBuilder::default();
+        mesh_builder.set_merge_quads(merge_quads);
+        add_inner_tiles(&chunk, &mut mesh_builder);
+
+        MeshLayer::LAYERS.map(|layer| {
+            let mut mesh = empty_mesh();
+            mesh_builder.finish(layer, &mut mesh);
+            mesh
+        })
+    }
+
+    fn attribute<T: From<[f32; 3]>>(mesh: &Mesh, id: impl Into<MeshVertexAttributeId>) -> Vec<T> {
+        match mesh.attribute(id) {
+            Some(VertexAttributeValues::Float32x3(values)) => {
+                values.iter().map(|&v| T::from(v)).collect()
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn occlusion(mesh: &Mesh) -> &[f32] {
+        match mesh.attribute(ATTRIBUTE_OCCLUSION) {
+            Some(VertexAttributeValues::Float32(values)) => values,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Like `build_meshes`, but returns the vertices of every triangle
+    fn mesh_tiles(tiles: &[([u8; 3], Tile)], merge_quads: bool) -> Vertices {
+        vertices(build_meshes(tiles, merge_quads))
+    }
+
+    fn vertices(meshes: [Mesh; 2]) -> Vertices {
+        let mut vertices = Vertices::default();
+        for mesh in meshes {
+            let position = attribute::<Vec3>(&mesh, Mesh::ATTRIBUTE_POSITION);
+            let normal = attribute::<Vec3>(&mesh, Mesh::ATTRIBUTE_NORMAL);
+            let uv = mesh.attribute(Mesh::ATTRIBUTE_UV_0);
+            let Some(VertexAttributeValues::Float32x2(uv)) = uv else { unreachable!() };
+            let occlusion = occlusion(&mesh);
+            for i in mesh.indices().unwrap().iter() {
+                vertices.position.push(position[i]);
+                vertices.normal.push(normal[i]);
+                vertices.uv.push(Vec2::from(uv[i]));
+                vertices.occlusion.push(occlusion[i]);
+            }
+        }
+        vertices
+    }
+
+    /// Bits of the vertices of each triangle in each layer, sorted so meshes
+    /// built in a different order compare equal
+    fn sorted_triangles(meshes: [Mesh; 2]) -> [Vec<Vec<u32>>; 2] {
+        meshes.map(|mesh| {
+            let v = vertices([mesh, empty_mesh()]);
+            let mut triangles = Vec::from_iter((0..v.position.len()).step_by(3).map(|i| {
+                let vertex = |j: usize| {
+                    let (p, n, uv, o) = (v.position[j], v.normal[j], v.uv[j], v.occlusion[j]);
+                    [p.x, p.y, p.z, n.x, n.y, n.z, uv.x, uv.y, o].map(f32::to_bits)
+                };
+                [vertex(i), vertex(i + 1), vertex(i + 2)].concat()
+            }));
+            triangles.sort();
+            triangles
+        })
+    }
+
+    /// Checks every triangle faces the same way as its normals, and every
+    /// edge is matched by an edge going the other way once edges are split at
+    /// the vertices lying on them
+    fn assert_watertight(mesh: &Vertices) {
+        for (p, n) in mesh.position.chunks(3).zip(mesh.normal.chunks(3)) {
+            let facing = (p[1] - p[0]).cross(p[2] - p[0]);
+            assert!(
+                facing.dot(n[0] + n[1] + n[2]) > 0.0,
+                "{p:?} faces away from {n:?}"
+            );
+        }
+
+        let vertices = mesh
+            .position
+            .iter()
+            .map(|&p| (p * 1024.0).round().as_ivec3())
+            .collect::<Vec<_>>();
+        // Every vertex on each axis aligned line
+        let mut lines = HashMap::<(usize, IVec3), Vec<i32>>::default();
+        for &v in &vertices {
+            for axis in 0..3 {
+                let mut key = v;
+                key[axis] = 0;
+                lines.entry((axis, key)).or_default().push(v[axis]);
+            }
+        }
+
+        let mut edges = HashMap::<(IVec3, IVec3), i32>::default();
+        for tri in vertices.chunks(3) {
+            for i in 0..3 {
+                let (a, b) = (tri[i], tri[(i + 1) % 3]);
+                let mut points = vec![a, b];
+                let diff = (b - a).to_array();
+                if let [axis] = (0..3).filter(|&i| diff[i] != 0).collect::<Vec<_>>()[..] {
+                    let mut key = a;
+                    key[axis] = 0;
+                    let (lo, hi) = (a[axis].min(b[axis]), a[axis].max(b[axis]));
+                    points.extend(
+                        lines[&(axis, key)]
+                            .iter()
+                            .filter(|&&c| lo < c && c < hi)
+                            .map(|&c| {
+                                let mut p = a;
+                                p[axis] = c;
+                                p
+                            }),
+                    );
+                    points.sort_by_key(|p| (p[axis] - a[axis]).abs());
+                    points.dedup();
+                }
+                for pair in points.windows(2) {
+                    *edges.entry((pair[0], pair[1])).or_default() += 1;
+                }
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            let reverse = edges.get(&(b, a)).copied().unwrap_or(0);
+            assert_eq!(reverse, count, "edge {a} -> {b} is not matched");
+        }
+    }
+
+    /// Axis, position, normal and uv of an axis aligned plane
+    type Plane = (usize, i32, IVec3, IVec2);
+
+    /// Coverage of points in planes, then the bits of every other triangle
+    type Raster = (HashMap<(Plane, IVec2), u32>, Vec<Vec<u32>>);
+
+    /// How many times triangles in axis aligned planes cover points spaced
+    /// 1/32 apart, and every other triangle
+    fn rasterize(mesh: &Vertices) -> Raster {
+        const SCALE: f64 = 32.0;
+        // Moved off the grid so points are never on the edge of a triangle
+        const JITTER: DVec2 = DVec2::new(FRAC_1_PI, FRAC_1_SQRT_2);
+
+        let mut coverage = HashMap::<(Plane, IVec2), u32>::default();
+        let mut other = Vec::new();
+        let triangles = mesh
+            .position
+            .chunks(3)
+            .zip(mesh.normal.chunks(3))
+            .zip(mesh.uv.chunks(3));
+        for ((p, n), uv) in triangles {
+            let flat = |axis: usize| p[0][axis] == p[1][axis] && p[1][axis] == p[2][axis];
+            let Some(axis) = (0..3).find(|&axis| flat(axis)) else {
+                let floats = p.iter().chain(n).flat_map(|v| v.to_array());
+                let floats = floats.chain(uv.iter().flat_map(|v| v.to_array()));
+                other.push(floats.map(f32::to_bits).collect());
+                continue;
+            };
+            let plane = (
+                axis,
+                (p[0][axis] * 1024.0).round() as i32,
+                n[0].round().as_ivec3(),
+                uv[0].round().as_ivec2(),
+            );
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            let corners = [0, 1, 2].map(|i| DVec2::new(p[i][u] as f64, p[i][v] as f64) * SCALE);
+            let min = corners.into_iter().reduce(DVec2::min).unwrap().floor();
+            let max = corners.into_iter().reduce(DVec2::max).unwrap().ceil();
+            let edge = |a: DVec2, b: DVec2, p: DVec2| (b - a).perp_dot(p - a);
+            for (i, j) in iproduct!(min.x as i32..max.x as i32, min.y as i32..max.y as i32) {
+                let point = DVec2::new(i as f64, j as f64) + JITTER;
+                let sides = [
+                    edge(corners[0], corners[1], point),
+                    edge(corners[1], corners[2], point),
+                    edge(corners[2], corners[0], point),
+                ];
+                if sides.iter().all(|&s| s > 0.0) || sides.iter().all(|&s| s < 0.0) {
+                    *coverage.entry((plane, IVec2::new(i, j))).or_default() += 1;
+                }
+            }
+        }
+        other.sort();
+        (coverage, other)
+    }
+
+    prop_compose! {
+        fn arb_solid_tile()(round: bool, color in 0..3_u8) -> Tile {
+            let mut tile = match round {
+                true => Tile::BRICK,
+                false => Tile::CONCRETE,
+            };
+            *tile.color_mut() = IndexedColor::new(color);
+            tile
+        }
+    }
+
+    prop_compose! {
+        fn arb_tile()(kind in 0..Tile::KINDS.len(), color in 0..3_u8) -> Tile {
+            let mut tile = Tile::KINDS[kind];
+            *tile.color_mut() = IndexedColor::new(color);
+            tile
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn solid_tiles_watertight(
+            tiles in proptest::collection::vec(([1..6_u8, 1..6_u8, 1..6_u8], arb_solid_tile()), 1..24)
+        ) {
+            assert_watertight(&mesh_tiles(&tiles, false));
+        }
+    }
+
+    proptest! {
+        // Rasterizing is slow, so fewer cases are run
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn merged_quads_cover_same_area(
+            tiles in proptest::collection::vec(([1..6_u8, 1..6_u8, 1..6_u8], arb_tile()), 1..24)
+        ) {
+            let merged = mesh_tiles(&tiles, true);
+            prop_assert_eq!(rasterize(&mesh_tiles(&tiles, false)), rasterize(&merged));
+            if tiles.iter().all(|(_, tile)| Tile::is_solid(Some(*tile))) {
+                assert_watertight(&merged);
+            }
+        }
+
+        #[test]
+        fn border_meshed_like_terrain(
+            tiles in proptest::collection::vec(([-3..19, -3..19, -3..19], arb_tile()), 0..64),
+            merge_quads: bool,
+        ) {
+            let mut terrain = Terrain::default();
+            for (pos, tile) in tiles {
+                terrain.set(GlobalPos::from_xyz_i32(pos), tile);
+            }
+            for chunk_pos in [ChunkPos::NEG_ONE, ChunkPos::ZERO] {
+                // Meshes every corner of the chunk by looking up the tiles
+                // around it in the terrain, in the same order as
+                // `add_inner_tiles`
+                let mut mesh_builder = MeshBuilder::default();
+                mesh_builder.set_merge_quads(merge_quads);
+                let origin = chunk_pos * i32::from(CHUNK_WIDTH);
+                for pos in LocalPos::positions() {
+                    let corner = origin + IVec3::from(pos.xyz().map(i32::from));
+                    let at = |[x, y, z]: [i32; 3]| {
+                        terrain.get(GlobalPos::from_xyz_i32(corner + IVec3::new(x, y, z)))
+                    };
+                    let tiles = CornerTiles([
+                        at([0, 0, 0]),
+                        at([0, 0, 1]),
+                        at([0, 1, 0]),
+                        at([0, 1, 1]),
+                        at([1, 0, 0]),
+                        at([1, 0, 1]),
+                        at([1, 1, 0]),
+                        at([1, 1, 1]),
+                    ]);
+                    generate_corner_mesh(tiles, pos.to_vec3(), &mut mesh_builder);
+                }
+                let expected = MeshLayer::LAYERS.map(|layer| {
+                    let mut mesh = empty_mesh();
+                    mesh_builder.finish(layer, &mut mesh);
+                    mesh
+                });
+
+                let neighbours = ChunkNeighbours::new(&terrain.chunks, chunk_pos, default());
+                prop_assert_eq!(
+                    sorted_triangles(neighbours.build_meshes(merge_quads, Lod::FULL, default())),
+                    sorted_triangles(expected)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn single_tile_golden_mesh() {
+        for (tile, vertices) in [
+            // 24 quads for the faces, 4 quads for each half of the edges
+            // and 16 triangles for each corner
+            (Tile::BRICK, 24 * 6 + 24 * 4 * 6 + 8 * 16 * 3),
+            // 1 quad for each half of the edges and 1 triangle for each corner
+            (Tile::CONCRETE, 24 * 6 + 24 * 6 + 8 * 3),
+            // Every quad has mortar along both of its outer sides
+            (Tile::GLASS, 24 * 3 * 6),
+            (Tile::WATER, 24 * 6),
+            (Tile::FOLIAGE, 24 * 6),
+        ] {
+            let mesh = mesh_tiles(&[([3, 3, 3], tile)], false);
+            assert_eq!(mesh.position.len(), vertices, "{tile:?}");
+            assert_watertight(&mesh);
+            for p in mesh.position {
+                assert!(p.cmpge(Vec3::splat(2.0)).all() && p.cmple(Vec3::splat(3.0)).all());
+            }
+        }
+    }
+
+    #[test]
+    fn shaped_tile_golden_mesh() {
+        let shaped = |shape| {
+            let mut tile = Tile::FOLIAGE;
+            *tile.shape_mut() = shape;
+            tile
+        };
+        for (shape, vertices) in [
+            // Full quads on the bottom, back and the low half of the sides,
+            // triangles on the rest of the sides and 4 quads on the slope
+            (Shape::Slope, 4 * 6 + 4 * 6 + 2 * 6 + 4 * 3 + 4 * 6),
+            // 18 quads on the outside and 4 on the steps
+            (Shape::Stairs, 18 * 6 + 4 * 6),
+        ] {
+            let mesh = mesh_tiles(&[([3, 3, 3], shaped(shape))], false);
+            assert_eq!(mesh.position.len(), vertices, "{shape:?}");
+            assert_watertight(&mesh);
+        }
+
+        let mesh = mesh_tiles(&[([3, 3, 3], shaped(Shape::SlopeCorner))], false);
+        assert_watertight(&mesh);
+        let up = Vec3::new(0.0, 1.0, 1.0).normalize();
+        assert!(mesh.normal.contains(&up));
+        for p in mesh.position {
+            assert!(p.cmpge(Vec3::splat(2.0)).all() && p.cmple(Vec3::splat(3.0)).all());
+        }
+    }
+
+    #[test]
+    fn slopes_hide_half_of_faces() {
+        let mut slope = Tile::CONCRETE;
+        *slope.shape_mut() = Shape::Slope;
+        let alone = mesh_tiles(&[([3, 3, 3], slope)], false).position.len();
+        let beside = |other: Tile| {
+            let mesh = mesh_tiles(&[([3, 3, 3], slope), ([4, 3, 3], other)], false);
+            assert_watertight(&mesh);
+            mesh.position.len()
+        };
+        // Slopes side by side hide each other's sides completely, while a
+        // glass slope only hides the low half of the side of a glass cube
+        let mut glass_slope = Tile::GLASS;
+        *glass_slope.shape_mut() = Shape::Slope;
+        assert!(beside(slope) < 2 * alone);
+        let glass = mesh_tiles(&[([3, 3, 3], glass_slope), ([4, 3, 3], Tile::GLASS)], false);
+        assert_watertight(&glass);
+        let seam = glass.position.chunks(3).zip(glass.normal.chunks(3));
+        let triangles = seam.filter(|(p, n)| p.iter().all(|p| p.x == 3.0) && n[0] == Vec3::NEG_X);
+        // The quarter the slope does not reach, and a triangle for each
+        // quarter it fills half of
+        assert_eq!(triangles.count(), 2 + 1 + 1);
+    }
+
+    #[test]
+    fn brick_is_rounded_cube() {
+        let mesh = mesh_tiles(&[([3, 3, 3], Tile::BRICK)], false);
+        // Every point on the surface of a cube with rounded edges is the
+        // radius away from the cube shrunk by the radius
+        let inner_min = Vec3::splat(2.0 + EDGE_RADIUS);
+        let inner_max = Vec3::splat(3.0 - EDGE_RADIUS);
+        for (p, n) in mesh.position.into_iter().zip(mesh.normal) {
+            let closest = p.clamp(inner_min, inner_max);
+            assert!((p.distance(closest) - EDGE_RADIUS).abs() < 1e-5, "{p}");
+            assert!((p - closest).normalize().distance(n) < 1e-5, "{p} {n}");
+        }
+    }
+
+    #[test]
+    fn mortar_between_different_tiles() {
+        let mut red = Tile::BRICK;
+        *red.color_mut() = IndexedColor::new(1);
+        let same = mesh_tiles(&[([3, 3, 3], Tile::BRICK), ([4, 3, 3], Tile::BRICK)], false);
+        let mesh = mesh_tiles(&[([3, 3, 3], Tile::BRICK), ([4, 3, 3], red)], false);
+        assert_watertight(&mesh);
+        // Both bricks have 2 quads on each of 4 faces along the seam
+        assert_eq!(mesh.position.len(), same.position.len() + 2 * 2 * 4 * 6);
+    }
+
+    #[test]
+    fn brick_on_concrete_capped() {
+        let mesh = mesh_tiles(
+            &[([3, 3, 3], Tile::BRICK), ([3, 2, 3], Tile::CONCRETE)],
+            false,
+        );
+        assert_watertight(&mesh);
+    }
+
+    proptest! {
+        #[test]
+        fn shaped_tiles_watertight(
+            tiles in proptest::collection::vec(
+                ([1..6_u8, 1..6_u8, 1..6_u8], arb_solid_tile(), arb_shape(), arb_orientation()),
+                1..24,
+            ),
+        ) {
+            let tiles = Vec::from_iter(tiles.into_iter().map(|(pos, mut tile, shape, orientation)| {
+                *tile.shape_mut() = shape;
+                *tile.orientation_mut() = orientation;
+                (pos, tile)
+            }));
+            assert_watertight(&mesh_tiles(&tiles, false));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn lod_meshes_watertight(
+            tiles in proptest::collection::vec(([0..16_u8, 0..16_u8, 0..16_u8], arb_solid_tile()), 1..64),
+            lod in 0..=Lod::MAX.0,
+        ) {
+            let mut chunk = Chunk::default();
+            for (pos, tile) in tiles {
+                let _ = chunk.set(LocalPos::new(pos).unwrap(), tile);
+            }
+            let chunks = HashMap::from_iter([(ChunkPos::ZERO, chunk)]);
+            // Closed on every side
+            let seams = Seams::new(Lod::FULL, |_| Lod::MAX);
+            let neighbours = ChunkNeighbours::new(&chunks, ChunkPos::ZERO, seams);
+            assert_watertight(&vertices(neighbours.build_meshes(false, Lod(lod), seams)));
+        }
+
+        #[test]
+        fn lod_seams_watertight(
+            tiles in proptest::collection::vec(([-6..6, -6..6, -6..6], arb_solid_tile()), 1..64),
+            lods: [bool; 8],
+        ) {
+            let mut terrain = Terrain::default();
+            for (pos, tile) in tiles {
+                terrain.set(GlobalPos::from_xyz_i32(pos), tile);
+            }
+            assert_watertight(&mesh_around_origin(&terrain, lods.map(u8::from)));
+        }
+    }
+
+    /// Meshes the chunks around the origin, with the 8 chunks touching it at
+    /// the levels in `lods` and every other chunk at full detail, and returns
+    /// the vertices of all of them where they are drawn
+    fn mesh_around_origin(terrain: &Terrain, lods: [u8; 8]) -> Vertices {
+        let lod_at = |pos: ChunkPos| {
+            let touching = pos.cmpge(ChunkPos::NEG_ONE).all() && pos.cmple(ChunkPos::ZERO).all();
+            let i = (pos + 1).dot(IVec3::new(4, 2, 1)) as usize;
+            match touching {
+                true => Lod(lods[i]),
+                false => Lod::FULL,
+            }
+        };
+        let mut mesh = Vertices::default();
+        for (x, y, z) in iproduct!(-2..=1, -2..=1, -2..=1) {
+            let pos = ChunkPos::new(x, y, z);
+            let lod = lod_at(pos);
+            let seams = Seams::new(lod, |offset| lod_at(pos + offset));
+            let neighbours = ChunkNeighbours::new(&terrain.chunks, pos, seams);
+            let chunk_mesh = vertices(neighbours.build_meshes(false, lod, seams));
+            let transform = lod.transform(pos);
+            let position = chunk_mesh.position.into_iter().map(|p| transform * p);
+            mesh.position.extend(position);
+            mesh.normal.extend(chunk_mesh.normal);
+        }
+        mesh
+    }
+
+    #[test]
+    fn diagonal_seams_watertight() {
+        let mut terrain = Terrain::default();
+        let [a, b] = [[-4; 3], [3; 3]].map(GlobalPos::from_xyz_i32);
+        terrain.fill_box(a, b, Tile::CONCRETE);
+        // Only one chunk coarser or finer than the rest, so it is only next
+        // to most of them along an edge or at a corner
+        for (i, lod) in iproduct!(0..8, [1, Lod::MAX.0]) {
+            let mut lods = [0; 8];
+            lods[i] = lod;
+            assert_watertight(&mesh_around_origin(&terrain, lods));
+            assert_watertight(&mesh_around_origin(&terrain, lods.map(|l| lod - l)));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn vertices_deduplicated(
+            tiles in proptest::collection::vec(([1..6_u8, 1..6_u8, 1..6_u8], arb_tile()), 1..24),
+            merge_quads: bool,
+        ) {
+            for mesh in build_meshes(&tiles, merge_quads) {
+                let position = attribute::<[f32; 3]>(&mesh, Mesh::ATTRIBUTE_POSITION);
+                let normal = attribute::<[f32; 3]>(&mesh, Mesh::ATTRIBUTE_NORMAL);
+                let uv = mesh.attribute(Mesh::ATTRIBUTE_UV_0);
+                let Some(VertexAttributeValues::Float32x2(uv)) = uv else { unreachable!() };
+                let mut vertices = itertools::izip!(position, normal, uv, occlusion(&mesh))
+                    .map(|(p, n, uv, o)| {
+                        [p[0], p[1], p[2], n[0], n[1], n[2], uv[0], uv[1], *o].map(f32::to_bits)
+                    })
+                    .collect::<Vec<_>>();
+                let len = vertices.len();
+                let indices = mesh.indices().unwrap();
+                prop_assert!(indices.iter().all(|i| i < len));
+                prop_assert_eq!(indices.len() % 3, 0);
+
+                vertices.sort();
+                vertices.dedup();
+                prop_assert_eq!(vertices.len(), len);
+            }
+        }
+    }
+
+    /// Every distinct vertex of `mesh`, rounded so tiny differences from
+    /// adding occlusion up in a different order are ignored
+    fn rounded_vertices(mesh: &Vertices) -> Vec<[i32; 7]> {
+        let mut vertices = Vec::from_iter((0..mesh.position.len()).map(|i| {
+            let (p, n, o) = (mesh.position[i], mesh.normal[i], mesh.occlusion[i]);
+            [p.x, p.y, p.z, n.x, n.y, n.z, o].map(|v| (v * 4096.0).round() as i32)
+        }));
+        vertices.sort();
+        vertices.dedup();
+        vertices
+    }
+
+    proptest! {
+        #[test]
+        fn turned_tiles_meshed_turned(
+            tiles in proptest::collection::vec(
+                ([1..8_u8, 1..8_u8, 1..8_u8], arb_tile(), arb_shape(), arb_orientation()),
+                1..16,
+            ),
+        ) {
+            // Turning every tile and where it is a quarter turn around y turns
+            // its mesh the same way
+            let (tiles, turned): (Vec<_>, Vec<_>) = tiles
+                .into_iter()
+                .map(|([x, y, z], mut tile, shape, orientation)| {
+                    *tile.shape_mut() = shape;
+                    *tile.orientation_mut() = orientation;
+                    (([x, y, z], tile), ([z, y, 9 - x], tile.rotated(IVec3::Y)))
+                })
+                .unzip();
+            let mut mesh = mesh_tiles(&tiles, false);
+            for p in &mut mesh.position {
+                *p = Vec3::new(p.z, p.y, 8.0 - p.x);
+            }
+            for n in &mut mesh.normal {
+                *n = Vec3::new(n.z, n.y, -n.x);
+            }
+            let turned = mesh_tiles(&turned, false);
+            prop_assert_eq!(turned.position.len(), mesh.position.len());
+            prop_assert_eq!(rounded_vertices(&turned), rounded_vertices(&mesh));
+        }
+    }
+
+    #[test]
+    fn quads_share_vertices() {
+        // Foliage has no mortar, so each side is a single quad
+        let [opaque, _] = build_meshes(&[([3, 3, 3], Tile::FOLIAGE)], true);
+        assert_eq!(opaque.count_vertices(), 6 * 4);
+        assert_eq!(opaque.indices().unwrap().len(), 6 * 6);
+    }
+
+    #[test]
+    fn foliage_only_meshed_outside() {
+        // The faces between the tiles are hidden and the rest merge, so it is
+        // meshed like a single tile
+        let tiles = [([3, 3, 3], Tile::FOLIAGE), ([4, 3, 3], Tile::FOLIAGE)];
+        let [opaque, _] = build_meshes(&tiles, true);
+        assert_eq!(opaque.count_vertices(), 6 * 4);
+    }
+
+    #[test]
+    fn inside_corners_occluded() {
+        // Open surfaces are not occluded
+        let mesh = mesh_tiles(&[([3, 3, 3], Tile::CONCRETE)], false);
+        assert!(mesh.occlusion.iter().all(|&o| o == 0.0));
+
+        // A floor from 1 to 6 along x, with a wall tile on it from 3 to 4
+        let mut tiles = Vec::from_iter((2..=6).map(|x| ([x, 2, 3], Tile::CONCRETE)));
+        tiles.push(([4, 3, 3], Tile::CONCRETE));
+        let mesh = mesh_tiles(&tiles, false);
+        let floor_occlusion = |x: f32| {
+            let pos = Vec3::new(x, 2.0, 2.5);
+            let i = (0..mesh.position.len())
+                .find(|&i| mesh.position[i] == pos && mesh.normal[i] == Vec3::Y)
+                .unwrap();
+            mesh.occlusion[i]
+        };
+        assert!(floor_occlusion(4.0) > 0.0);
+        assert_eq!(floor_occlusion(4.0), floor_occlusion(3.0));
+        assert_eq!(floor_occlusion(5.0), 0.0);
+        assert_eq!(floor_occlusion(2.0), 0.0);
+    }
+
+    #[test]
+    fn mesh_from_neighbours() {
+        let tiles = [
+            ([3, 3, 3], Tile::BRICK),
+            ([4, 3, 3], Tile::GLASS),
+            ([8, 9, 10], Tile::WATER),
+        ];
+        let mut chunk = Chunk::default();
+        for (pos, tile) in tiles {
+            let _ = chunk.set(LocalPos::new(pos).unwrap(), tile);
+        }
+        let chunks = HashMap::from_iter([(ChunkPos::ZERO, chunk)]);
+
+        let neighbours = ChunkNeighbours::new(&chunks, ChunkPos::ZERO, default());
+        assert_eq!(
+            vertices(neighbours.build_meshes(true, Lod::FULL, default())),
+            mesh_tiles(&tiles, true)
+        );
+        // Chunks before it share its corners, so also have meshes
+        let before = ChunkNeighbours::new(&chunks, ChunkPos::NEG_ONE, default());
+        assert!(!before.is_empty());
+        assert!(ChunkNeighbours::new(&chunks, ChunkPos::ONE, default()).is_empty());
+    }
+
+    #[test]
+    fn chunk_bounds_match_tiles() {
+        // Inside the tile at the origin, which is the first tile of its chunk
+        let eye = GlobalPos::from_xyz_i32([0; 3]).center();
+        assert_eq!(distance_to_chunk(eye, ChunkPos::ZERO), 0.0);
+        assert_eq!(distance_to_chunk(eye, ChunkPos::NEG_X), 0.5);
+        assert_eq!(distance_to_chunk(eye, ChunkPos::Z), 15.5);
+    }
+
+    #[test]
+    fn visible_changed_chunks_first() {
+        let behind = ChunkPos::new(0, 0, 1);
+        let in_front = ChunkPos::new(0, 0, -3);
+        let beside = ChunkPos::new(-2, 0, -1);
+        let mut changed = HashSet::from_iter([behind, in_front, beside]);
+        // Looking along -Z from the origin
+        let projection = Mat4::perspective_infinite_reverse_rh(FRAC_PI_4, 1.0, 0.1);
+        let frustum = Frustum::from_view_projection(&projection, &Vec3::ZERO, &Vec3::Z, 1000.0);
+        let camera = Some((Vec3::ZERO, &frustum));
+
+        assert_eq!(take_changed(&mut changed, camera, 1), [in_front]);
+        assert_eq!(take_changed(&mut changed, camera, 1), [behind]);
+        assert_eq!(take_changed(&mut changed, camera, 1), [beside]);
+        assert!(changed.is_empty());
+
+        // Either side of the eye and just as far from it
+        let (left, right) = (ChunkPos::NEG_X, ChunkPos::X);
+        let mut changed = HashSet::from_iter([right, left]);
+        let eye = Vec3::splat(7.0);
+        let frustum = Frustum::from_view_projection(&projection, &eye, &Vec3::Z, 1000.0);
+        let camera = Some((eye, &frustum));
+        assert_eq!(take_changed(&mut changed, camera, 1), [left]);
+        assert_eq!(take_changed(&mut changed, camera, 1), [right]);
+    }
+
+    #[test]
+    fn coarse_tiles_most_common() {
+        let mut chunk = Chunk::default();
+        let mut set = |pos, tile| {
+            let _ = chunk.set(LocalPos::new(pos).unwrap(), tile);
+        };
+        // Filled, with more bricks than concrete
+        set([0, 0, 0], Tile::BRICK);
+        set([0, 1, 0], Tile::BRICK);
+        set([1, 1, 1], Tile::BRICK);
+        set([1, 0, 0], Tile::CONCRETE);
+        set([0, 0, 1], Tile::CONCRETE);
+        // Less than half filled
+        set([2, 0, 0], Tile::BRICK);
+        set([3, 1, 0], Tile::BRICK);
+        set([3, 1, 1], Tile::BRICK);
+
+        let coarse = Coarse::new(&chunk, 2);
+        assert_eq!(coarse.tile(IVec3::ZERO), Some(Tile::BRICK));
+        assert_eq!(coarse.tile(IVec3::X), None);
+        assert_eq!(coarse.tile(IVec3::NEG_X), None);
+    }
+
+    #[test]
+    fn lod_by_distance() {
+        let mut settings = LodSettings {
+            enabled: true,
+            distance: 100.0,
+        };
+        assert_eq!(Lod::new(99.0, &settings), Lod::FULL);
+        assert_eq!(Lod::new(100.0, &settings), Lod(1));
+        assert_eq!(Lod::new(250.0, &settings), Lod(2));
+        assert_eq!(Lod::new(1e6, &settings), Lod::MAX);
+        settings.enabled = false;
+        assert_eq!(Lod::new(1e6, &settings), Lod::FULL);
+    }
+
+    #[test]
+    fn merged_cube() {
+        let tiles = iproduct!(1..=12, 1..=12, 1..=12)
+            .map(|(x, y, z)| ([x, y, z], Tile::BRICK))
+            .collect::<Vec<_>>();
+        let mesh = mesh_tiles(&tiles, true);
+        assert_watertight(&mesh);
+        // Each side of the cube is a single quad, only the rounded edges and
+        // corners are left
+        let edges = 12 * 12 * 2 * 4 * 6;
+        let corners = 8 * 16 * 3;
+        assert_eq!(mesh.position.len(), 6 * 6 + edges + corners);
+        let unmerged = mesh_tiles(&tiles, false);
+        assert_eq!(unmerged.position.len(), 12 * 12 * 24 * 6 + edges + corners);
+    }
 }
@@ -0,0 +1,307 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashSet};
+use bevy_flycam::FlyCam;
+use bitflags::bitflags;
+use itertools::iproduct;
+
+use crate::terrain::{
+    chunk::{Chunk, ChunkPos, LocalPos, CHUNK_AREA, CHUNK_WIDTH},
+    tile::Tile,
+    GlobalPos, Terrain,
+};
+
+use super::generate_meshes_system;
+
+pub struct CullingPlugin;
+
+impl Plugin for CullingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Culling>()
+            .add_system(cull_chunks_system.after(generate_meshes_system));
+    }
+}
+
+/// Hides chunks that cannot be seen from the camera through open space
+#[derive(Debug, Resource)]
+pub struct Culling {
+    pub enabled: bool,
+    /// How many chunks were hidden the last time culling ran
+    pub culled: usize,
+}
+
+impl Default for Culling {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            culled: 0,
+        }
+    }
+}
+
+bitflags! {
+    /// Sides of a chunk
+    pub struct Faces: u8 {
+        const NEG_X = 0b000001;
+        const POS_X = 0b000010;
+        const NEG_Y = 0b000100;
+        const POS_Y = 0b001000;
+        const NEG_Z = 0b010000;
+        const POS_Z = 0b100000;
+    }
+}
+
+impl Faces {
+    /// Each side and the direction it faces
+    const SIDES: [(Faces, IVec3); 6] = [
+        (Faces::NEG_X, IVec3::NEG_X),
+        (Faces::POS_X, IVec3::X),
+        (Faces::NEG_Y, IVec3::NEG_Y),
+        (Faces::POS_Y, IVec3::Y),
+        (Faces::NEG_Z, IVec3::NEG_Z),
+        (Faces::POS_Z, IVec3::Z),
+    ];
+
+    /// Index of a single side in `SIDES`
+    fn index(self) -> usize {
+        self.bits().trailing_zeros() as usize
+    }
+
+    /// The sides facing the other way
+    fn opposite(self) -> Self {
+        let negative = self.bits() & 0b010101;
+        let positive = self.bits() & 0b101010;
+        Self::from_bits_truncate(negative << 1 | positive >> 1)
+    }
+
+    /// Sides of the chunk the tile at `pos` touches
+    fn touched_by(pos: LocalPos) -> Self {
+        let mut faces = Self::empty();
+        for (axis, coord) in pos.xyz().into_iter().enumerate() {
+            let (negative, positive) = (Self::SIDES[axis * 2].0, Self::SIDES[axis * 2 + 1].0);
+            faces.set(negative, coord == 0);
+            faces.set(positive, coord == CHUNK_WIDTH - 1);
+        }
+        faces
+    }
+}
+
+/// Which sides of a chunk can be seen from each other through it, along
+/// paths of tiles that are not solid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkConnections([Faces; 6]);
+
+impl ChunkConnections {
+    /// Connections of a chunk without any solid tiles
+    pub const OPEN: Self = Self([Faces::all(); 6]);
+
+    pub fn new(chunk: &Chunk) -> Self {
+        let mut connections = [Faces::empty(); 6];
+        let mut visited = vec![false; CHUNK_AREA];
+        let mut stack = Vec::new();
+        for start in LocalPos::positions() {
+            if visited[start.bits() as usize] || Tile::is_solid(chunk[start]) {
+                continue;
+            }
+            // Flood fills the open space around `start`, connecting every
+            // side it reaches to each other
+            visited[start.bits() as usize] = true;
+            stack.push(start);
+            let mut touched = Faces::empty();
+            while let Some(pos) = stack.pop() {
+                touched |= Faces::touched_by(pos);
+                let pos_ivec3 = IVec3::from(pos.xyz().map(i32::from));
+                for (_, offset) in Faces::SIDES {
+                    let Some(next) = LocalPos::from_ivec3(pos_ivec3 + offset) else { continue };
+                    if !visited[next.bits() as usize] && !Tile::is_solid(chunk[next]) {
+                        visited[next.bits() as usize] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+            for (face, _) in Faces::SIDES {
+                if touched.contains(face) {
+                    connections[face.index()] |= touched;
+                }
+            }
+        }
+        Self(connections)
+    }
+
+    /// Whether a path through the chunk leads from side `from` to side `to`
+    pub fn connects(self, from: Faces, to: Faces) -> bool {
+        self.0[from.index()].contains(to)
+    }
+}
+
+fn cull_chunks_system(
+    terrain: Res<Terrain>,
+    mut culling: ResMut<Culling>,
+    camera: Query<&GlobalTransform, With<FlyCam>>,
+    mut visibility: Query<&mut Visibility>,
+) {
+    let reached = match (culling.enabled, camera.get_single()) {
+        (true, Ok(transform)) => {
+            // The tile at `pos` fills the cube from `pos - 1` to `pos`
+            let eye = transform.translation().floor().as_ivec3() + IVec3::ONE;
+            let start = GlobalPos::from_xyz_i32(eye).chunk;
+            let connections = |pos| {
+                terrain
+                    .mesh_ids
+                    .get(&pos)
+                    .map_or(ChunkConnections::OPEN, |chunk_meshes| {
+                        chunk_meshes.connections
+                    })
+            };
+            let bounds = terrain
+                .mesh_ids
+                .keys()
+                .fold((start, start), |(min, max), &pos| {
+                    (min.min(pos), max.max(pos))
+                });
+            Some(reachable_chunks(start, bounds, connections))
+        }
+        _ => None,
+    };
+
+    let mut culled = 0;
+    for (&pos, chunk_meshes) in &terrain.mesh_ids {
+        // The mesh of a chunk has tiles from the chunks around it, so it is
+        // shown if any of them can be seen
+        let visible = match &reached {
+            Some(reached) => iproduct!(-1..=1, -1..=1, -1..=1)
+                .any(|(x, y, z)| reached.contains(&(pos + IVec3::new(x, y, z)))),
+            None => true,
+        };
+        culled += usize::from(!visible);
+        for (entity, _) in &chunk_meshes.layers {
+            // Meshes spawned this frame have not been added yet
+            let Ok(mut visibility) = visibility.get_mut(*entity) else { continue };
+            if visibility.is_visible != visible {
+                visibility.is_visible = visible;
+            }
+        }
+    }
+    culling.culled = culled;
+}
+
+/// Chunks that can be seen from the chunk at `start` through open space,
+/// without leaving the box between `bounds` grown by a chunk.  Paths never
+/// turn back towards `start`, so they cannot reach chunks behind walls by
+/// going around them
+fn reachable_chunks(
+    start: ChunkPos,
+    (min, max): (ChunkPos, ChunkPos),
+    connections: impl Fn(ChunkPos) -> ChunkConnections,
+) -> HashSet<ChunkPos> {
+    let (min, max) = (min - IVec3::ONE, max + IVec3::ONE);
+    let mut reached = HashSet::from_iter([start]);
+    let mut queue = VecDeque::from([(start, None::<Faces>, Faces::empty())]);
+    while let Some((pos, entered, travelled)) = queue.pop_front() {
+        let connections = connections(pos);
+        for (face, offset) in Faces::SIDES {
+            let next = pos + offset;
+            let open = match entered {
+                Some(entered) => connections.connects(entered, face),
+                None => true,
+            };
+            let inside = next.cmpge(min).all() && next.cmple(max).all();
+            if open && inside && !travelled.contains(face.opposite()) && reached.insert(next) {
+                queue.push_back((next, Some(face.opposite()), travelled | face));
+            }
+        }
+    }
+    reached
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use proptest::prelude::*;
+
+    use crate::terrain::{
+        chunk::{Chunk, LocalPos, CHUNK_WIDTH},
+        tile::Tile,
+    };
+
+    use super::{reachable_chunks, ChunkConnections, Faces};
+
+    /// Chunk with every tile where `x` is `at` filled
+    fn wall(at: u8, tile: Tile) -> Chunk {
+        let mut chunk = Chunk::default();
+        let (lo, hi) = ([at, 0, 0], [at, CHUNK_WIDTH - 1, CHUNK_WIDTH - 1]);
+        let _ = chunk.edit_box(
+            LocalPos::new(lo).unwrap(),
+            LocalPos::new(hi).unwrap(),
+            |_, _| Some(tile),
+        );
+        chunk
+    }
+
+    #[test]
+    fn walls_split_chunks() {
+        assert_eq!(
+            ChunkConnections::new(&Chunk::default()),
+            ChunkConnections::OPEN
+        );
+
+        let connections = ChunkConnections::new(&wall(8, Tile::CONCRETE));
+        assert!(!connections.connects(Faces::NEG_X, Faces::POS_X));
+        assert!(connections.connects(Faces::NEG_X, Faces::POS_Y));
+        assert!(connections.connects(Faces::POS_X, Faces::NEG_Z));
+        assert!(connections.connects(Faces::NEG_Y, Faces::POS_Y));
+
+        // Walls on the side cut off only that side
+        let connections = ChunkConnections::new(&wall(0, Tile::BRICK));
+        assert!(!connections.connects(Faces::NEG_X, Faces::POS_X));
+        assert!(!connections.connects(Faces::POS_X, Faces::NEG_X));
+        assert!(connections.connects(Faces::POS_X, Faces::NEG_Y));
+
+        // Glass can be seen through
+        let connections = ChunkConnections::new(&wall(8, Tile::GLASS));
+        assert_eq!(connections, ChunkConnections::OPEN);
+    }
+
+    #[test]
+    fn walls_hide_chunks() {
+        let bounds = (IVec3::ZERO, IVec3::new(5, 0, 0));
+        let reached = reachable_chunks(IVec3::ZERO, bounds, |_| ChunkConnections::OPEN);
+        assert_eq!(reached.len(), 8 * 3 * 3);
+
+        // A wall across the start of every chunk where x is 2
+        let walled = ChunkConnections::new(&wall(0, Tile::CONCRETE));
+        let connections = |pos: IVec3| match pos.x == 2 {
+            true => walled,
+            false => ChunkConnections::OPEN,
+        };
+        let reached = reachable_chunks(IVec3::ZERO, bounds, connections);
+        assert!(reached.contains(&IVec3::new(2, 0, 0)));
+        assert!(reached.contains(&IVec3::new(2, 1, -1)));
+        assert!(!reached.contains(&IVec3::new(3, 0, 0)));
+        assert!(!reached.contains(&IVec3::new(3, 1, 0)));
+
+        // A wall in a single chunk only hides the chunks straight behind it
+        let connections = |pos: IVec3| match pos == IVec3::new(2, 0, 0) {
+            true => walled,
+            false => ChunkConnections::OPEN,
+        };
+        let reached = reachable_chunks(IVec3::ZERO, bounds, connections);
+        assert!(!reached.contains(&IVec3::new(3, 0, 0)));
+        assert!(reached.contains(&IVec3::new(3, 1, 0)));
+    }
+
+    proptest! {
+        #[test]
+        fn connections_symmetric(tiles in prop::collection::vec(([..CHUNK_WIDTH; 3], any::<bool>()), 0..512)) {
+            let mut chunk = Chunk::default();
+            for (pos, solid) in tiles {
+                let tile = if solid { Tile::CONCRETE } else { Tile::GLASS };
+                let _ = chunk.set(LocalPos::new(pos).unwrap(), tile);
+            }
+            let connections = ChunkConnections::new(&chunk);
+            for ((a, _), (b, _)) in itertools::iproduct!(Faces::SIDES, Faces::SIDES) {
+                prop_assert_eq!(connections.connects(a, b), connections.connects(b, a));
+            }
+        }
+    }
+}
@@ -5,7 +5,15 @@ use bevy::{
 use bevy_egui::{egui, EguiContext};
 
 use crate::terrain::{
-    tile::{color::IndexedColor, Tile},
+    history::History,
+    mesh::{
+        culling::Culling, lighting::TerrainLighting, LodSettings, MeshBudget, MeshSettings,
+        MeshStats,
+    },
+    palette::{Palette, Palettes, Swatches},
+    pick::SelectedTile,
+    save::DEFAULT_WORLD_PATH,
+    tile::Tile,
     GlobalPos, Terrain,
 };
 
@@ -14,17 +22,30 @@ pub struct InspectPlugin;
 impl Plugin for InspectPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(WireframePlugin)
-            .add_system(inspect_mesh_system);
+            .add_system(inspect_mesh_system)
+            .add_system(inspect_lighting_system);
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn inspect_mesh_system(
     mut egui_context: ResMut<EguiContext>,
     mut wireframe_config: ResMut<WireframeConfig>,
     mut terrain: ResMut<Terrain>,
+    mut history: ResMut<History>,
     mut cube_size: Local<isize>,
-    mut tile_type: Local<Tile>,
+    mut tile_type: ResMut<SelectedTile>,
+    mut world_path: Local<String>,
+    mut palettes: ResMut<Palettes>,
+    palette_assets: Res<Assets<Palette>>,
+    mut swatches: ResMut<Swatches>,
+    mut settings: ResMut<MeshSettings>,
+    mut budget: ResMut<MeshBudget>,
+    mut lod_settings: ResMut<LodSettings>,
+    mut culling: ResMut<Culling>,
+    meshes: Res<Assets<Mesh>>,
 ) {
+    let palette = palette_assets.get(&palettes.current);
     egui::Window::new("Mesh Inspector")
         .open(&mut true)
         .default_width(200.0)
@@ -33,33 +54,63 @@ fn inspect_mesh_system(
             ui.vertical_centered_justified(|ui| {
                 ui.heading("Terrain");
 
-                ui.add(Tile::widget(&mut tile_type));
+                ui.add(tile_type.widget(&mut swatches));
 
                 ui.add_space(4.0);
 
                 if ui.button("Clear").clicked() {
-                    terrain.clear();
+                    history.record(&mut terrain, "Clear", Terrain::clear);
                 };
 
-                if ui.button("Tower").clicked() {
-                    terrain.clear();
-                    for y in -24..=24 {
-                        terrain.set(GlobalPos::from_xyz_i32(IVec3::new(1, y, 1)), *tile_type);
+                ui.add(egui::TextEdit::singleline(&mut *world_path).hint_text(DEFAULT_WORLD_PATH));
+                let path = match world_path.as_str() {
+                    "" => DEFAULT_WORLD_PATH,
+                    path => path,
+                };
+                ui.columns(2, |columns| {
+                    if columns[0].button("Save").clicked() {
+                        match terrain.save_to_file(path) {
+                            Ok(()) => info!("Saved world to {path}"),
+                            Err(e) => error!("Failed to save world to {path}: {e}"),
+                        }
+                    }
+                    if columns[1].button("Load").clicked() {
+                        match terrain.load_from_file(path) {
+                            Ok(()) => {
+                                history.clear();
+                                info!("Loaded world from {path}");
+                            }
+                            Err(e) => error!("Failed to load world from {path}: {e}"),
+                        }
                     }
+                });
+
+                ui.add_space(4.0);
+
+                if ui.button("Tower").clicked() {
+                    history.record(&mut terrain, "Tower", |terrain| {
+                        terrain.clear();
+                        terrain.fill_box(
+                            GlobalPos::from_xyz_i32([1, -24, 1]),
+                            GlobalPos::from_xyz_i32([1, 24, 1]),
+                            tile_type.0,
+                        );
+                    });
                 }
 
                 ui.horizontal(|ui| {
                     ui.add(egui::DragValue::new(&mut *cube_size).clamp_range(1..=64));
                     ui.centered_and_justified(|ui| {
                         if ui.button("Cube").clicked() {
-                            terrain.clear();
-                            for x in -4..(*cube_size as i32 - 4) {
-                                for y in -4..(*cube_size as i32 - 4) {
-                                    for z in -4..(*cube_size as i32 - 4) {
-                                        terrain.set(GlobalPos::from_xyz_i32([x, y, z]), *tile_type);
-                                    }
-                                }
-                            }
+                            history.record(&mut terrain, "Cube", |terrain| {
+                                terrain.clear();
+                                let size = *cube_size as i32 - 5;
+                                terrain.fill_box(
+                                    GlobalPos::from_xyz_i32([-4; 3]),
+                                    GlobalPos::from_xyz_i32([size; 3]),
+                                    tile_type.0,
+                                );
+                            });
                         }
                     });
                 });
@@ -68,68 +119,199 @@ fn inspect_mesh_system(
                     ui.add(egui::DragValue::new(&mut *cube_size).clamp_range(1..=64));
                     ui.centered_and_justified(|ui| {
                         if ui.button("Cube Outline").clicked() {
-                            terrain.clear();
-                            let size = *cube_size as i32 - 4;
-                            for x in -4..=size {
-                                terrain.set(GlobalPos::from_xyz_i32([x, -4, -4]), *tile_type);
-                                terrain.set(GlobalPos::from_xyz_i32([x, -4, size]), *tile_type);
-                                terrain.set(GlobalPos::from_xyz_i32([x, size, -4]), *tile_type);
-                                terrain.set(GlobalPos::from_xyz_i32([x, size, size]), *tile_type);
-                            }
-                            for y in -4..=size {
-                                terrain.set(GlobalPos::from_xyz_i32([-4, y, -4]), *tile_type);
-                                terrain.set(GlobalPos::from_xyz_i32([-4, y, size]), *tile_type);
-                                terrain.set(GlobalPos::from_xyz_i32([size, y, -4]), *tile_type);
-                                terrain.set(GlobalPos::from_xyz_i32([size, y, size]), *tile_type);
-                            }
-                            for z in -4..=size {
-                                terrain.set(GlobalPos::from_xyz_i32([-4, -4, z]), *tile_type);
-                                terrain.set(GlobalPos::from_xyz_i32([-4, size, z]), *tile_type);
-                                terrain.set(GlobalPos::from_xyz_i32([size, -4, z]), *tile_type);
-                                terrain.set(GlobalPos::from_xyz_i32([size, size, z]), *tile_type);
-                            }
+                            history.record(&mut terrain, "Cube Outline", |terrain| {
+                                terrain.clear();
+                                let size = *cube_size as i32 - 4;
+                                terrain.fill_box(
+                                    GlobalPos::from_xyz_i32([-4; 3]),
+                                    GlobalPos::from_xyz_i32([size; 3]),
+                                    tile_type.0,
+                                );
+                                // Every tile with at least two coordinates inside
+                                // the cube is not on an edge
+                                if size > -3 {
+                                    for axis in 0..3 {
+                                        let mut min = [-3; 3];
+                                        let mut max = [size - 1; 3];
+                                        min[axis] = -4;
+                                        max[axis] = size;
+                                        terrain.clear_box(
+                                            GlobalPos::from_xyz_i32(min),
+                                            GlobalPos::from_xyz_i32(max),
+                                        );
+                                    }
+                                }
+                            });
                         }
                     });
                 });
 
                 if ui.button("\"MESH\"").clicked() {
-                    terrain.clear();
-                    let mut draw_line = |mut bits: u32, y, z| {
-                        while bits != 0 {
-                            let x = bits.trailing_zeros();
-                            bits ^= 1 << x;
-                            terrain.set(GlobalPos::from_xyz_i32([x as i32, y, z]), *tile_type);
-                        }
-                    };
-                    draw_line(0b10001000000000000, 0, 6);
-                    draw_line(0b11011000000000000, 0, 5);
-                    draw_line(0b10101011101110101, 0, 4);
-                    draw_line(0b10001010001000101, 0, 3);
-                    draw_line(0b10001011101110111, 0, 2);
-                    draw_line(0b10001010000010101, 0, 1);
-                    draw_line(0b10001011101110101, 0, 0);
-                    draw_line(0b10001000000000000, 1, 6);
-                    draw_line(0b11011000000000000, 1, 5);
-                    draw_line(0b10101000000000000, 1, 4);
-                    draw_line(0b10001000000000000, 1, 3);
-                    draw_line(0b10001000000000000, 1, 2);
-                    draw_line(0b10001000000000000, 1, 1);
-                    draw_line(0b10001000000000000, 1, 0);
+                    history.record(&mut terrain, "\"MESH\"", |terrain| {
+                        terrain.clear();
+                        let mut draw_line = |mut bits: u32, y, z| {
+                            while bits != 0 {
+                                let x = bits.trailing_zeros();
+                                bits ^= 1 << x;
+                                terrain.set(GlobalPos::from_xyz_i32([x as i32, y, z]), tile_type.0);
+                            }
+                        };
+                        draw_line(0b10001000000000000, 0, 6);
+                        draw_line(0b11011000000000000, 0, 5);
+                        draw_line(0b10101011101110101, 0, 4);
+                        draw_line(0b10001010001000101, 0, 3);
+                        draw_line(0b10001011101110111, 0, 2);
+                        draw_line(0b10001010000010101, 0, 1);
+                        draw_line(0b10001011101110101, 0, 0);
+                        draw_line(0b10001000000000000, 1, 6);
+                        draw_line(0b11011000000000000, 1, 5);
+                        draw_line(0b10101000000000000, 1, 4);
+                        draw_line(0b10001000000000000, 1, 3);
+                        draw_line(0b10001000000000000, 1, 2);
+                        draw_line(0b10001000000000000, 1, 1);
+                        draw_line(0b10001000000000000, 1, 0);
+                    });
                 }
 
                 ui.add_space(4.0);
 
-                if ui.button("Colors").clicked() {
-                    terrain.clear();
-                    for color in (0..).map_while(IndexedColor::from_index) {
-                        let [x, y] = color.uv().map(|v| (v * 8.0 - 0.5) as i32);
-                        terrain.set(GlobalPos::from_xyz_i32([x, 0, y]), Tile::Brick { color })
-                    }
+                let colors = ui.add_enabled(palette.is_some(), egui::Button::new("Colors"));
+                if let (true, Some(palette)) = (colors.clicked(), palette) {
+                    history.record(&mut terrain, "Colors", |terrain| {
+                        terrain.clear();
+                        for color in palette.colors() {
+                            let [x, y] = palette.grid_pos(color).map(i32::from);
+                            let mut tile = Tile::BRICK;
+                            *tile.color_mut() = color;
+                            terrain.set(GlobalPos::from_xyz_i32([x, 0, y]), tile)
+                        }
+                    });
                 }
             });
 
             ui.separator();
 
+            let mut picked = None;
+            egui::ComboBox::from_label("Palette")
+                .selected_text(palette.map_or("Loading...", |palette| &palette.name))
+                .show_ui(ui, |ui| {
+                    for (handle, palette) in palettes.loaded(&palette_assets) {
+                        let selected = handle == palettes.current;
+                        if ui.selectable_label(selected, &palette.name).clicked() {
+                            picked = Some(handle);
+                        }
+                    }
+                });
+            // Only assigned when it differs so the materials are not
+            // updated every frame
+            if let Some(handle) = picked.filter(|handle| *handle != palettes.current) {
+                palettes.current = handle;
+            }
+
+            let (chunks, bytes) = terrain.memory_usage();
+            ui.label(format!("{chunks} chunks ({} KiB)", bytes / 1024));
+            let stats = MeshStats::new(&terrain, &meshes);
+            ui.label(format!(
+                "{} vertices, {} indices ({} KiB)",
+                stats.vertices,
+                stats.indices,
+                stats.bytes() / 1024
+            ))
+            .on_hover_text(format!(
+                "{} vertices ({} KiB) without deduplication",
+                stats.indices,
+                stats.unindexed_bytes() / 1024
+            ));
             ui.checkbox(&mut wireframe_config.global, "Wireframe");
+            // Only assigned when it differs so chunks are not remeshed every
+            // frame
+            let mut merge_quads = settings.merge_quads;
+            ui.checkbox(&mut merge_quads, "Merge Quads")
+                .on_hover_text("Merge quads in the same plane with the same color");
+            if merge_quads != settings.merge_quads {
+                settings.merge_quads = merge_quads;
+            }
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut budget.chunks_per_frame).clamp_range(1..=1024));
+                ui.label("Chunks per Frame")
+                    .on_hover_text("Most chunks to start meshing each frame");
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut lod_settings.enabled, "LOD")
+                    .on_hover_text("Mesh distant chunks at lower resolutions");
+                ui.add_enabled(
+                    lod_settings.enabled,
+                    egui::DragValue::new(&mut lod_settings.distance)
+                        .clamp_range(16.0..=1024.0)
+                        .suffix(" tiles"),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut culling.enabled, "Culling")
+                    .on_hover_text("Hide chunks the camera cannot see through open space");
+                if culling.enabled {
+                    ui.label(format!("{} chunks culled", culling.culled));
+                }
+            });
+        });
+}
+
+fn inspect_lighting_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut lighting: ResMut<TerrainLighting>,
+) {
+    egui::Window::new("Lighting")
+        .open(&mut true)
+        .default_width(200.0)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            // Edited on a copy so the lighting is only marked as changed, and
+            // the materials updated, when something was actually changed
+            let TerrainLighting {
+                mut sun_angle,
+                mut sun_intensity,
+                mut ambient,
+                mut cycle,
+                mut time_of_day,
+                mut day_length,
+            } = *lighting;
+            ui.add_enabled(
+                !cycle,
+                egui::Slider::new(&mut sun_angle, -90.0..=270.0)
+                    .suffix("°")
+                    .text("Sun Angle"),
+            )
+            .on_hover_text("Angle of the sun above the horizon");
+            ui.add(egui::Slider::new(&mut sun_intensity, 0.0..=1.0).text("Sun"));
+            ui.add(egui::Slider::new(&mut ambient, 0.0..=1.0).text("Ambient"));
+            ui.separator();
+            ui.checkbox(&mut cycle, "Day/Night Cycle");
+            let time = ui.add(
+                egui::Slider::new(&mut time_of_day, 0.0..=24.0)
+                    .suffix(" h")
+                    .text("Time of Day"),
+            );
+            ui.add_enabled(
+                cycle,
+                egui::Slider::new(&mut day_length, 10.0..=1200.0)
+                    .logarithmic(true)
+                    .suffix(" s")
+                    .text("Day Length"),
+            );
+
+            let edited = TerrainLighting {
+                sun_angle,
+                sun_intensity,
+                ambient,
+                cycle,
+                time_of_day: lighting.time_of_day,
+                day_length,
+            };
+            if edited != *lighting {
+                *lighting = edited;
+            }
+            if time.changed() {
+                lighting.set_time_of_day(time_of_day);
+            }
         });
 }
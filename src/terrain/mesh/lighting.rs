@@ -0,0 +1,159 @@
+use bevy::{prelude::*, render::render_resource::ShaderType};
+
+use super::{OpaqueTerrainMaterial, TerrainMaterialHandles, TransparentTerrainMaterial};
+
+/// Direction of the sun at noon
+const NOON: Vec3 = Vec3::new(0.0, 0.917, 0.4);
+/// How much of the ambient light is left once the sun has set
+const NIGHT_AMBIENT: f32 = 0.3;
+/// How far above the horizon in degrees the sun is when it reaches full
+/// brightness
+const DAWN_ANGLE: f32 = 15.0;
+
+pub struct LightingPlugin;
+
+impl Plugin for LightingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainLighting>()
+            .add_system(day_night_cycle_system)
+            .add_system(update_lighting_system.after(day_night_cycle_system));
+    }
+}
+
+/// How the terrain is lit
+#[derive(Debug, Clone, Copy, PartialEq, Resource)]
+pub struct TerrainLighting {
+    /// Angle of the sun above the horizon in degrees.  It rises in +x and sets
+    /// in -x, tilted towards +z
+    pub sun_angle: f32,
+    /// Light added to surfaces facing towards or directly away from the sun
+    pub sun_intensity: f32,
+    /// Light added to every surface during the day
+    pub ambient: f32,
+    /// Whether the time of day advances on its own
+    pub cycle: bool,
+    /// Hours since midnight, from 0 to 24
+    pub time_of_day: f32,
+    /// How many seconds a whole day and night lasts while cycling
+    pub day_length: f32,
+}
+
+impl Default for TerrainLighting {
+    fn default() -> Self {
+        Self {
+            sun_angle: 90.0,
+            sun_intensity: 0.2,
+            ambient: 0.8,
+            cycle: false,
+            time_of_day: 12.0,
+            day_length: 120.0,
+        }
+    }
+}
+
+impl TerrainLighting {
+    /// Sets the time and moves the sun to where it is at that time, rising at
+    /// 6:00 and setting at 18:00
+    pub fn set_time_of_day(&mut self, hours: f32) {
+        self.time_of_day = hours.rem_euclid(24.0);
+        self.sun_angle = (self.time_of_day - 6.0) * 15.0;
+    }
+
+    /// Unit vector pointing towards the sun
+    pub fn sun_direction(&self) -> Vec3 {
+        let (sin, cos) = self.sun_angle.to_radians().sin_cos();
+        Vec3::X * cos + NOON.normalize() * sin
+    }
+
+    /// How bright the sun is, from 0 below the horizon to 1 once it has
+    /// risen `DAWN_ANGLE` above it
+    fn daylight(&self) -> f32 {
+        let height = self.sun_angle.to_radians().sin();
+        (height / DAWN_ANGLE.to_radians().sin()).clamp(0.0, 1.0)
+    }
+}
+
+/// Lighting as it is given to the terrain shaders
+#[derive(Debug, Default, Clone, Copy, PartialEq, ShaderType)]
+pub(super) struct LightingUniform {
+    sun_direction: Vec3,
+    sun_intensity: f32,
+    ambient: f32,
+}
+
+impl From<&TerrainLighting> for LightingUniform {
+    fn from(lighting: &TerrainLighting) -> Self {
+        let daylight = lighting.daylight();
+        Self {
+            sun_direction: lighting.sun_direction(),
+            sun_intensity: lighting.sun_intensity * daylight,
+            ambient: lighting.ambient * (NIGHT_AMBIENT + (1.0 - NIGHT_AMBIENT) * daylight),
+        }
+    }
+}
+
+fn day_night_cycle_system(time: Res<Time>, mut lighting: ResMut<TerrainLighting>) {
+    // Checked first so the lighting is not marked as changed every frame
+    if !lighting.cycle {
+        return;
+    }
+    let hours = lighting.time_of_day + time.delta_seconds() * 24.0 / lighting.day_length;
+    lighting.set_time_of_day(hours);
+}
+
+fn update_lighting_system(
+    lighting: Res<TerrainLighting>,
+    materials: Res<TerrainMaterialHandles>,
+    mut opaque_materials: ResMut<Assets<OpaqueTerrainMaterial>>,
+    mut transparent_materials: ResMut<Assets<TransparentTerrainMaterial>>,
+) {
+    if !lighting.is_changed() {
+        return;
+    }
+    let uniform = LightingUniform::from(&*lighting);
+    opaque_materials
+        .get_mut(&materials.opaque)
+        .unwrap()
+        .lighting = uniform;
+    transparent_materials
+        .get_mut(&materials.transparent)
+        .unwrap()
+        .lighting = uniform;
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::{LightingUniform, TerrainLighting};
+
+    #[test]
+    fn default_lighting_unchanged() {
+        // The values the shaders used before lighting could be changed
+        let uniform = LightingUniform::from(&TerrainLighting::default());
+        assert!(uniform
+            .sun_direction
+            .abs_diff_eq(Vec3::new(0.0, 0.917, 0.4), 1e-3));
+        assert_eq!(uniform.sun_intensity, 0.2);
+        assert_eq!(uniform.ambient, 0.8);
+    }
+
+    #[test]
+    fn sun_follows_time_of_day() {
+        let mut lighting = TerrainLighting::default();
+        let mut direction_at = |hours| {
+            lighting.set_time_of_day(hours);
+            lighting.sun_direction()
+        };
+        assert!(direction_at(6.0).abs_diff_eq(Vec3::X, 1e-5));
+        assert!(direction_at(18.0).abs_diff_eq(Vec3::NEG_X, 1e-5));
+        assert!(direction_at(0.0).y < 0.0);
+        assert!(direction_at(36.0).abs_diff_eq(direction_at(12.0), 1e-5));
+
+        lighting.set_time_of_day(-1.0);
+        assert_eq!(lighting.time_of_day, 23.0);
+        let night = LightingUniform::from(&lighting);
+        assert_eq!(night.sun_intensity, 0.0);
+        assert!(night.ambient > 0.0 && night.ambient < lighting.ambient);
+    }
+}
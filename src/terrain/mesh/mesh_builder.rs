@@ -1,89 +1,210 @@
-use std::iter;
+use std::{array, mem};
 
-use bevy::{prelude::*, render::mesh::VertexAttributeValues};
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, MeshVertexAttribute},
+        render_resource::VertexFormat,
+    },
+    utils::HashMap,
+};
 use bitflags::bitflags;
 
+use crate::terrain::tile::orientation::Orientation;
+
+use self::quad::Quad;
+
 use super::Subtile;
 
-#[derive(Debug)]
-pub struct MeshBuilder<'a> {
-    position: &'a mut Vec<[f32; 3]>,
-    normal: &'a mut Vec<[f32; 3]>,
-    uv: &'a mut Vec<[f32; 2]>,
-    offset: Vec3,
+mod quad;
+
+/// Which of the meshes of a chunk geometry is added to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MeshLayer {
+    #[default]
+    Opaque,
+    /// Alpha blended, drawn after all opaque geometry
+    Transparent,
 }
 
-impl<'a> MeshBuilder<'a> {
-    pub(super) fn edit(mesh: &'a mut Mesh) -> Self {
-        let mut position = None;
-        let mut normal = None;
-        let mut uv = None;
-
-        for (id, values) in mesh.attributes_mut() {
-            if id == Mesh::ATTRIBUTE_POSITION.id {
-                let VertexAttributeValues::Float32x3(p) = values else { panic!(
-                    "position should be `Float32x3` but is `{}``",
-                    values.enum_variant_name()
-                ) };
-                position = Some(p);
-            } else if id == Mesh::ATTRIBUTE_NORMAL.id {
-                let VertexAttributeValues::Float32x3(n) = values else { panic!(
-                    "normal should be `Float32x3` but is `{}``",
-                    values.enum_variant_name()
-                ) };
-                normal = Some(n);
-            } else if id == Mesh::ATTRIBUTE_UV_0.id {
-                let VertexAttributeValues::Float32x2(p) = values else { panic!(
-                    "uv should be `Float32x2` but is `{}``",
-                    values.enum_variant_name()
-                ) };
-                uv = Some(p);
-            } else {
-                panic!("Unexpected mesh attribute: {id:?}")
-            }
-        }
+impl MeshLayer {
+    pub const LAYERS: [Self; 2] = [Self::Opaque, Self::Transparent];
+}
 
-        let position = position.expect("Terrain mesh missing position");
-        let normal = normal.expect("Terrain mesh missing normal");
-        let uv = uv.expect("Terrain mesh missing uv");
+/// How much each vertex is darkened by the solid tiles around it, from 0 to 1
+pub const ATTRIBUTE_OCCLUSION: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Occlusion", 2_618_337_541, VertexFormat::Float32);
 
-        position.clear();
-        normal.clear();
-        uv.clear();
-        Self {
-            position,
-            normal,
-            uv,
-            offset: Vec3::ZERO,
+#[derive(Debug, Default)]
+pub struct MeshBuilder {
+    buffers: [MeshBuffers; 2],
+    layer: MeshLayer,
+    offset: Vec3,
+    /// How geometry is turned before it is moved to the corner
+    orientation: Orientation,
+    /// Which of the tiles around the corner darken the geometry near them
+    occluders: [bool; 8],
+    /// Whether to merge quads with their neighbours when finishing
+    merge_quads: bool,
+}
+
+impl MeshBuilder {
+    /// Gives the vertex buffers of `layer` to `mesh`
+    pub(super) fn finish(&mut self, layer: MeshLayer, mesh: &mut Mesh) {
+        let mut buffers = mem::take(&mut self.buffers[layer as usize]);
+        if self.merge_quads {
+            quad::merge_quads(&mut buffers.quads);
         }
+        buffers.restore(mesh);
+    }
+
+    pub(super) fn set_merge_quads(&mut self, merge_quads: bool) {
+        self.merge_quads = merge_quads;
     }
 
     pub(super) fn set_offset(&mut self, offset: Vec3) {
         self.offset = offset;
     }
 
+    /// Sets how geometry is turned, so tiles can be meshed as if they were
+    /// upright
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+    }
+
+    pub(super) fn set_occluders(&mut self, occluders: [bool; 8]) {
+        self.occluders = occluders;
+    }
+
+    /// Sets the layer geometry is added to
+    pub fn set_layer(&mut self, layer: MeshLayer) {
+        self.layer = layer;
+    }
+
     pub fn add<T: MeshObject>(&mut self, object: T, uv: [f32; 2], subtile: Subtile, pos: T::Pos) {
         object.add_to_mesh(self, uv, subtile, pos);
     }
 
-    fn add_tri(&mut self, position: [Vec3; 3], normal: Vec3, uv: [f32; 2]) {
-        self.position
-            .extend(position.into_iter().map(|p| (p + self.offset).to_array()));
-        self.normal.extend(iter::repeat(normal.to_array()).take(3));
-        self.uv.extend([uv; 3]);
+    /// Adds a triangle with a normal for each vertex, flipping it if needed
+    /// so it faces the same way as the normals
+    fn add_smooth_tri(&mut self, mut position: [Vec3; 3], mut normal: [Vec3; 3], uv: [f32; 2]) {
+        if self.orientation != Orientation::DEFAULT {
+            position = position.map(|p| self.orientation.apply(p));
+            normal = normal.map(|n| self.orientation.apply(n));
+        }
+        let facing = (position[1] - position[0]).cross(position[2] - position[0]);
+        if facing.dot(normal[0] + normal[1] + normal[2]) < 0.0 {
+            position.swap(1, 2);
+            normal.swap(1, 2);
+        }
+        let occlusion = array::from_fn(|i| self.occlusion(position[i], normal[i]));
+        let position = position.map(|p| p + self.offset);
+        self.buffers[self.layer as usize].add_tri(position, normal, uv, occlusion);
     }
 
-    fn add_quad(&mut self, position: [Vec3; 4], normal: Vec3, uv: [f32; 2]) {
-        self.add_tri([position[0], position[1], position[3]], normal, uv);
-        self.add_tri([position[2], position[3], position[1]], normal, uv);
+    /// Adds an axis aligned rectangle
+    fn add_quad(&mut self, mut position: [Vec3; 4], mut normal: Vec3, uv: [f32; 2]) {
+        if self.orientation != Orientation::DEFAULT {
+            position = position.map(|p| self.orientation.apply(p));
+            normal = self.orientation.apply(normal);
+        }
+        let occlusion = position.map(|p| self.occlusion(p, normal));
+        let position = position.map(|p| p + self.offset);
+        self.buffers[self.layer as usize]
+            .quads
+            .push(Quad::new(position, normal, uv, occlusion));
+    }
+
+    /// Ambient occlusion of a vertex at `position` in the corner.  Each
+    /// occluding tile in front of the vertex darkens it fully when touching it,
+    /// fading out half a tile away, and three are needed to fully occlude it
+    fn occlusion(&self, position: Vec3, normal: Vec3) -> f32 {
+        let mut occlusion = 0.0;
+        for subtile in Subtile::subtiles() {
+            if !self.occluders[subtile.bits() as usize] {
+                continue;
+            }
+            let side = subtile.offset();
+            // Tiles behind the vertex are the ones it is on the surface of
+            if (side * 0.25 - position).dot(normal) <= 0.0 {
+                continue;
+            }
+            let nearest = position.clamp(side.min(Vec3::ZERO) * 0.5, side.max(Vec3::ZERO) * 0.5);
+            occlusion += (1.0 - 2.0 * position.distance(nearest)).max(0.0);
+        }
+        (occlusion / 3.0).min(1.0)
     }
 }
 
+#[derive(Debug, Default)]
+struct MeshBuffers {
+    position: Vec<[f32; 3]>,
+    normal: Vec<[f32; 3]>,
+    uv: Vec<[f32; 2]>,
+    occlusion: Vec<f32>,
+    indices: Vec<u32>,
+    /// Index of every vertex by the bits of its position, normal, uv and
+    /// occlusion, so identical vertices are only added once
+    vertex_ids: HashMap<[u32; 9], u32>,
+    /// Added to the other buffers once the mesh is finished
+    quads: Vec<Quad>,
+}
+
+impl MeshBuffers {
+    fn add_tri(
+        &mut self,
+        position: [Vec3; 3],
+        normal: [Vec3; 3],
+        uv: [f32; 2],
+        occlusion: [f32; 3],
+    ) {
+        for ((position, normal), occlusion) in position.into_iter().zip(normal).zip(occlusion) {
+            let [px, py, pz] = position.to_array();
+            let [nx, ny, nz] = normal.to_array();
+            let key = [px, py, pz, nx, ny, nz, uv[0], uv[1], occlusion].map(f32::to_bits);
+            let index = *self.vertex_ids.entry(key).or_insert_with(|| {
+                self.position.push([px, py, pz]);
+                self.normal.push([nx, ny, nz]);
+                self.uv.push(uv);
+                self.occlusion.push(occlusion);
+                self.position.len() as u32 - 1
+            });
+            self.indices.push(index);
+        }
+    }
+
+    fn restore(mut self, mesh: &mut Mesh) {
+        for quad in mem::take(&mut self.quads) {
+            quad.add_to(&mut self);
+        }
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.position);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normal);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uv);
+        mesh.insert_attribute(ATTRIBUTE_OCCLUSION, self.occlusion);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+    }
+}
+
+/// How far rounded edges cut into the faces next to them
+pub const EDGE_RADIUS: f32 = 0.125;
+/// Number of quads around each rounded edge
+const ROUND_SEGMENTS: usize = 4;
+/// Width of the mortar along the unconnected sides of walls
+const MORTAR_WIDTH: f32 = 0.03125;
+
 #[derive(Debug, Default)]
 pub enum Face {
     #[default]
     None,
     Wall(WallFlags),
+    /// A wall cut back by `EDGE_RADIUS` from the sides along each axis in
+    /// the set, to make room for rounded edges
+    RoundedWall(WallFlags, Subtile),
+    /// A wall whose tile is filled to `depth` below its top, used for liquids
+    Liquid(f32),
+    /// Only some parts of a wall, where the rest is hidden or not filled by
+    /// the shape of the tile
+    Partial(FaceParts),
 }
 
 impl MeshObject for Face {
@@ -95,20 +216,221 @@ impl MeshObject for Face {
             tangent,
             bitangent,
         } = pos.directions(subtile);
-        let side = |a, b| a * 0.5 + b * 0.5;
-        mesh.add_quad(
-            [
-                side(tangent, bitangent),
-                side(Vec3::ZERO, bitangent),
-                side(Vec3::ZERO, Vec3::ZERO),
-                side(tangent, Vec3::ZERO),
-            ],
+        if let Face::Partial(parts) = self {
+            parts.add_triangles(mesh, uv, tangent, bitangent, Vec3::ZERO, normal);
+            return;
+        }
+        let trim = |dir: Vec3| match self {
+            Face::RoundedWall(_, rounded) if rounded.contains(Subtile::from_axis(dir)) => {
+                EDGE_RADIUS
+            }
+            _ => 0.0,
+        };
+        let (t, b) = (trim(tangent), trim(bitangent));
+        let flags = match self {
+            Face::Wall(flags) | Face::RoundedWall(flags, _) => flags,
+            _ => WallFlags::CONNECTED_ALL,
+        };
+        // Rounded sides are already set apart, so only flat ones need mortar
+        let mortar = |dir: Vec3, trim: f32| {
+            let connected = flags.contains(WallFlags::side(pos, -dir));
+            match trim == 0.0 && !connected {
+                true => MORTAR_WIDTH,
+                false => 0.0,
+            }
+        };
+        let (mt, mb) = (mortar(tangent, t), mortar(bitangent, b));
+        let side = |u: f32, v: f32| tangent * u + bitangent * v;
+        let rect = |[u0, u1]: [f32; 2], [v0, v1]: [f32; 2]| {
+            [side(u1, v1), side(u0, v1), side(u0, v0), side(u1, v0)]
+        };
+        let mut position = rect([t + mt, 0.5], [b + mb, 0.5]);
+        // Only the top half of a tile is below the corner
+        if let Face::Liquid(depth) = self {
+            if !subtile.contains(Subtile::Y) {
+                for p in &mut position {
+                    p.y = p.y.min(-depth);
+                }
+            }
+        }
+        mesh.add_quad(position, normal, uv);
+
+        let mortar_uv = [uv[0], 1.0];
+        if mt > 0.0 {
+            mesh.add_quad(rect([t, t + mt], [b, 0.5]), normal, mortar_uv);
+        }
+        if mb > 0.0 {
+            mesh.add_quad(rect([t + mt, 0.5], [b, b + mb]), normal, mortar_uv);
+        }
+    }
+}
+
+bitflags! {
+    /// Parts of the side of a subtile, which its diagonals split into four
+    /// triangles.  Each is named after the edge of the side it touches, where
+    /// `U` runs along the tangent and `V` along the bitangent, starting at
+    /// the corner
+    pub struct FaceParts: u8 {
+        const U_START = 0b0001;
+        const U_END = 0b0010;
+        const V_START = 0b0100;
+        const V_END = 0b1000;
+    }
+}
+
+impl FaceParts {
+    /// A point inside each part, as `U` and `V`
+    const POINTS: [(Self, Vec2); 4] = [
+        (Self::U_START, Vec2::new(0.125, 0.25)),
+        (Self::U_END, Vec2::new(0.375, 0.25)),
+        (Self::V_START, Vec2::new(0.25, 0.125)),
+        (Self::V_END, Vec2::new(0.25, 0.375)),
+    ];
+
+    /// A point inside each part of the side of `subtile` facing along `face`,
+    /// moved `depth` into the tile from the corner
+    pub fn points(face: SubtileFace, subtile: Subtile, depth: f32) -> [(Self, Vec3); 4] {
+        let Directions {
             normal,
-            uv,
-        );
+            tangent,
+            bitangent,
+        } = face.directions(subtile);
+        Self::POINTS.map(|(part, p)| (part, tangent * p.x + bitangent * p.y - normal * depth))
+    }
+
+    /// Triangles covering the parts, as `U` and `V`.  Two parts next to each
+    /// other make half of the side, which is covered by a single triangle
+    fn triangles(self) -> Vec<[Vec2; 3]> {
+        let [start, u_end, end, v_end] =
+            [[0.0, 0.0], [0.5, 0.0], [0.5, 0.5], [0.0, 0.5]].map(Vec2::from);
+        let middle = Vec2::splat(0.25);
+        let halves = [
+            (Self::U_START | Self::V_START, [start, u_end, v_end]),
+            (Self::U_START | Self::V_END, [start, end, v_end]),
+            (Self::U_END | Self::V_START, [start, u_end, end]),
+            (Self::U_END | Self::V_END, [u_end, end, v_end]),
+        ];
+        if self.is_all() {
+            return vec![[start, u_end, end], [start, end, v_end]];
+        }
+        if let Some(&(_, half)) = halves.iter().find(|&&(parts, _)| parts == self) {
+            return vec![half];
+        }
+        let parts = [
+            (Self::U_START, [start, v_end]),
+            (Self::U_END, [u_end, end]),
+            (Self::V_START, [start, u_end]),
+            (Self::V_END, [v_end, end]),
+        ];
+        parts
+            .into_iter()
+            .filter(|&(part, _)| self.contains(part))
+            .map(|(_, [a, b])| [a, b, middle])
+            .collect()
+    }
+
+    /// Adds the parts of the side with the given directions, moved `offset`
+    /// from the corner
+    fn add_triangles(
+        self,
+        mesh: &mut MeshBuilder,
+        uv: [f32; 2],
+        tangent: Vec3,
+        bitangent: Vec3,
+        offset: Vec3,
+        normal: Vec3,
+    ) {
+        for triangle in self.triangles() {
+            let position = triangle.map(|p| tangent * p.x + bitangent * p.y + offset);
+            mesh.add_smooth_tri(position, [normal; 3], uv);
+        }
     }
 }
 
+/// A wall through the middle of a tile that is not a cube, covering the parts
+/// of the side of the subtile facing away from the corner, like the front of
+/// a step
+#[derive(Debug)]
+pub struct Step(pub FaceParts);
+
+impl MeshObject for Step {
+    type Pos = SubtileFace;
+
+    fn add_to_mesh(self, mesh: &mut MeshBuilder, uv: [f32; 2], subtile: Subtile, pos: Self::Pos) {
+        let Directions {
+            normal,
+            tangent,
+            bitangent,
+        } = pos.directions(subtile);
+        self.0
+            .add_triangles(mesh, uv, tangent, bitangent, -normal * 0.5, -normal);
+    }
+}
+
+/// A flat surface through the center of a tile that is not a cube, along the
+/// plane whose normal it is added at.  Only the part in the subtile and behind
+/// every other plane through the center of the tile in `clip` is added
+#[derive(Debug)]
+pub struct Slope<'a>(pub &'a [Vec3]);
+
+impl MeshObject for Slope<'_> {
+    type Pos = Vec3;
+
+    fn add_to_mesh(self, mesh: &mut MeshBuilder, uv: [f32; 2], subtile: Subtile, pos: Self::Pos) {
+        let center = subtile.offset() * 0.5;
+        // A square on the plane large enough to cover the subtile
+        let tangent = pos.cross(if pos.x == 0.0 { Vec3::X } else { Vec3::Y });
+        let bitangent = pos.cross(tangent);
+        let mut polygon = [(1.0, 1.0), (-1.0, 1.0), (-1.0, -1.0), (1.0, -1.0)]
+            .map(|(u, v)| center + (tangent * u + bitangent * v) * 2.0)
+            .to_vec();
+        // The subtile is the box between the corner and the center of the tile
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            let into_tile = axis * center.dot(axis).signum();
+            polygon = clip(polygon, into_tile, 0.5);
+            polygon = clip(polygon, -into_tile, 0.0);
+        }
+        for &plane in self.0.iter().filter(|&&plane| plane != pos) {
+            polygon = clip(polygon, plane, plane.dot(center));
+        }
+
+        // Points are always on a grid, so tiny errors from clipping are
+        // rounded away
+        for p in &mut polygon {
+            *p = (*p * 64.0).round() / 64.0;
+        }
+        polygon.dedup();
+        if polygon.len() > 1 && polygon[0] == polygon[polygon.len() - 1] {
+            polygon.pop();
+        }
+        let normal = pos.normalize();
+        for i in 2..polygon.len() {
+            let triangle = [polygon[0], polygon[i - 1], polygon[i]];
+            let area = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]);
+            if area.length_squared() > 1e-6 {
+                mesh.add_smooth_tri(triangle, [normal; 3], uv);
+            }
+        }
+    }
+}
+
+/// Cuts away the part of the convex `polygon` in front of the plane where
+/// `normal.dot(p) == distance`
+fn clip(polygon: Vec<Vec3>, normal: Vec3, distance: f32) -> Vec<Vec3> {
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for (i, &a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let (da, db) = (normal.dot(a) - distance, normal.dot(b) - distance);
+        if da <= 0.0 {
+            clipped.push(a);
+        }
+        if (da < 0.0 && db > 0.0) || (da > 0.0 && db < 0.0) {
+            clipped.push(a + (b - a) * (da / (da - db)));
+        }
+    }
+    clipped
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SubtileFace {
     X,
@@ -204,6 +526,9 @@ pub struct Directions {
 }
 
 bitflags! {
+    /// Which sides of a wall continue into a matching wall.  Looking at the
+    /// wall, north and east are the positive directions of the axes returned
+    /// by `WallFlags::axes`
     pub struct WallFlags: u8 {
         const CONNECTED_N = 0b00001;
         const CONNECTED_E = 0b00010;
@@ -222,6 +547,27 @@ impl Default for WallFlags {
 }
 
 impl WallFlags {
+    /// The east and north directions of walls facing along `face`
+    fn axes(face: SubtileFace) -> [Vec3; 2] {
+        match face {
+            SubtileFace::X => [Vec3::Z, Vec3::Y],
+            SubtileFace::Y => [Vec3::X, Vec3::Z],
+            SubtileFace::Z => [Vec3::X, Vec3::Y],
+        }
+    }
+
+    /// Flag for the side of a wall facing along `face` in direction `dir`
+    pub fn side(face: SubtileFace, dir: Vec3) -> Self {
+        let [east, north] = WallFlags::axes(face);
+        match dir {
+            _ if dir == north => Self::CONNECTED_N,
+            _ if dir == east => Self::CONNECTED_E,
+            _ if dir == -north => Self::CONNECTED_S,
+            _ if dir == -east => Self::CONNECTED_W,
+            _ => panic!("{dir} is not a side of walls facing along {face:?}"),
+        }
+    }
+
     pub fn set_facing_positive(self, subtile: Subtile, axis: Subtile) -> Self {
         if subtile.contains(axis) {
             self | Self::FACING_POSITIVE
@@ -231,22 +577,177 @@ impl WallFlags {
     }
 }
 
-#[derive(Debug, Default)]
+/// Shape of the outer edges and corners of a tile
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Edge {
     #[default]
     None,
+    /// A flat cut between the faces
+    Bevel,
     Round,
 }
 
+impl Edge {
+    fn segments(self) -> usize {
+        match self {
+            Edge::None => 0,
+            Edge::Bevel => 1,
+            Edge::Round => ROUND_SEGMENTS,
+        }
+    }
+
+    /// Direction from the center of the edge to its surface, blending the
+    /// outward normals of the faces by integer weights so both sides of a
+    /// seam get exactly the same points
+    fn blend(weights: [usize; 3], normals: [Vec3; 3]) -> Vec3 {
+        (normals[0] * weights[0] as f32
+            + normals[1] * weights[1] as f32
+            + normals[2] * weights[2] as f32)
+            .normalize()
+    }
+
+    /// Bevels are flat, so use the same normal across each triangle
+    fn flatten(self, normals: [Vec3; 3]) -> [Vec3; 3] {
+        match self {
+            Edge::Bevel => [(normals[0] + normals[1] + normals[2]).normalize(); 3],
+            _ => normals,
+        }
+    }
+
+    /// Adds the edge between the faces along `a` and `b`, from `start` to
+    /// 0.5 along `along`
+    fn add_segment(
+        self,
+        mesh: &mut MeshBuilder,
+        uv: [f32; 2],
+        subtile: Subtile,
+        [a, b, along]: [Subtile; 3],
+        start: f32,
+    ) {
+        let [into_a, into_b, into_along] = [a, b, along].map(|axis| subtile.into_tile(axis));
+        let center = (into_a + into_b) * EDGE_RADIUS;
+        let segments = self.segments();
+        let arc = (0..=segments)
+            .map(|i| Edge::blend([segments - i, i, 0], [-into_a, -into_b, Vec3::ZERO]))
+            .collect::<Vec<_>>();
+        for n in arc.windows(2) {
+            let point = |n: Vec3, t: f32| center + n * EDGE_RADIUS + into_along * t;
+            mesh.add_smooth_tri(
+                [point(n[0], start), point(n[1], start), point(n[0], 0.5)],
+                self.flatten([n[0], n[1], n[0]]),
+                uv,
+            );
+            mesh.add_smooth_tri(
+                [point(n[1], start), point(n[1], 0.5), point(n[0], 0.5)],
+                self.flatten([n[1], n[1], n[0]]),
+                uv,
+            );
+        }
+    }
+
+    /// Adds the corner where the faces along all three axes meet
+    fn add_corner(self, mesh: &mut MeshBuilder, uv: [f32; 2], subtile: Subtile) {
+        let normals = [Subtile::X, Subtile::Y, Subtile::Z].map(|axis| -subtile.into_tile(axis));
+        let center = -(normals[0] + normals[1] + normals[2]) * EDGE_RADIUS;
+        let segments = self.segments();
+        let normal = |i: usize, j: usize| Edge::blend([segments - i - j, i, j], normals);
+        let point = |n: Vec3| center + n * EDGE_RADIUS;
+        for i in 0..segments {
+            for j in 0..(segments - i) {
+                let n = [normal(i, j), normal(i + 1, j), normal(i, j + 1)];
+                mesh.add_smooth_tri(n.map(point), self.flatten(n), uv);
+                if j + 1 < segments - i {
+                    let n = [normal(i + 1, j), normal(i + 1, j + 1), normal(i, j + 1)];
+                    mesh.add_smooth_tri(n.map(point), self.flatten(n), uv);
+                }
+            }
+        }
+    }
+}
+
+/// The edges and corner of a subtile between every pair of exposed faces
+impl MeshObject for Edge {
+    /// Axes of the faces of the subtile that are exposed
+    type Pos = Subtile;
+
+    fn add_to_mesh(
+        self,
+        mesh: &mut MeshBuilder,
+        uv: [f32; 2],
+        subtile: Subtile,
+        exposed: Self::Pos,
+    ) {
+        if self == Edge::None {
+            return;
+        }
+        for edge in SubtileEdge::EDGES {
+            let [a, b, along] = edge.axes();
+            if exposed.contains(a | b) {
+                // The corner fills the start of the edge
+                let start = match exposed.contains(along) {
+                    true => EDGE_RADIUS,
+                    false => 0.0,
+                };
+                self.add_segment(mesh, uv, subtile, edge.axes(), start);
+            }
+        }
+        if exposed.is_all() {
+            self.add_corner(mesh, uv, subtile);
+        }
+    }
+}
+
+/// Closes the end of a rounded edge that runs into a tile that does not
+/// continue it.  Uses the uv of that tile, as the cap is part of its face
+#[derive(Debug)]
+pub struct EdgeCap(pub Edge);
+
+impl MeshObject for EdgeCap {
+    type Pos = SubtileEdge;
+
+    fn add_to_mesh(self, mesh: &mut MeshBuilder, uv: [f32; 2], subtile: Subtile, pos: Self::Pos) {
+        let [a, b, along] = pos.axes();
+        let [into_a, into_b] = [a, b].map(|axis| subtile.into_tile(axis));
+        let center = (into_a + into_b) * EDGE_RADIUS;
+        let normal = subtile.into_tile(along);
+        let segments = self.0.segments();
+        let arc = (0..=segments)
+            .map(|i| {
+                center
+                    + Edge::blend([segments - i, i, 0], [-into_a, -into_b, Vec3::ZERO])
+                        * EDGE_RADIUS
+            })
+            .collect::<Vec<_>>();
+        for p in arc.windows(2) {
+            mesh.add_smooth_tri([Vec3::ZERO, p[0], p[1]], [normal; 3], uv);
+        }
+    }
+}
+
 pub trait MeshObject {
     type Pos;
 
     fn add_to_mesh(self, mesh: &mut MeshBuilder, uv: [f32; 2], subtile: Subtile, pos: Self::Pos);
 }
 
+/// An edge of a subtile, named after the axes of the faces it is between
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SubtileEdge {
     XY,
     XZ,
     ZY,
 }
+
+impl SubtileEdge {
+    pub const EDGES: [Self; 3] = [Self::XY, Self::XZ, Self::ZY];
+
+    /// Axes of the two faces the edge is between, then the axis it runs
+    /// along
+    pub fn axes(self) -> [Subtile; 3] {
+        match self {
+            SubtileEdge::XY => [Subtile::X, Subtile::Y, Subtile::Z],
+            SubtileEdge::XZ => [Subtile::X, Subtile::Z, Subtile::Y],
+            SubtileEdge::ZY => [Subtile::Z, Subtile::Y, Subtile::X],
+        }
+    }
+}
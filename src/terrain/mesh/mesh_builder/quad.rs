@@ -0,0 +1,140 @@
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+
+use super::MeshBuffers;
+
+/// An axis aligned rectangle.  These are kept apart from the rest of the mesh
+/// until it is finished, so they can be merged with their neighbours
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Quad {
+    /// Axis of the normal, then the axes the rectangle spans in the order
+    /// that makes it face along the normal
+    axes: [usize; 3],
+    /// Position along the normal
+    depth: f32,
+    min: Vec2,
+    max: Vec2,
+    normal: Vec3,
+    uv: [f32; 2],
+    /// Occlusion of each corner, in the order they are added to the mesh
+    occlusion: [f32; 4],
+}
+
+impl Quad {
+    /// Quad with the corners in `position`, which should be an axis aligned
+    /// rectangle, and the occlusion of each of them
+    pub fn new(position: [Vec3; 4], normal: Vec3, uv: [f32; 2], occlusion: [f32; 4]) -> Self {
+        let axis = (0..3)
+            .max_by(|&a, &b| normal[a].abs().total_cmp(&normal[b].abs()))
+            .unwrap();
+        let axes = match normal[axis] > 0.0 {
+            true => [axis, (axis + 1) % 3, (axis + 2) % 3],
+            false => [axis, (axis + 2) % 3, (axis + 1) % 3],
+        };
+        let min = position.into_iter().reduce(Vec3::min).unwrap();
+        let max = position.into_iter().reduce(Vec3::max).unwrap();
+        let mut sorted_occlusion = [0.0; 4];
+        for (p, occlusion) in position.into_iter().zip(occlusion) {
+            let corner = match (p[axes[1]] == max[axes[1]], p[axes[2]] == max[axes[2]]) {
+                (false, false) => 0,
+                (true, false) => 1,
+                (true, true) => 2,
+                (false, true) => 3,
+            };
+            sorted_occlusion[corner] = occlusion;
+        }
+        Self {
+            axes,
+            depth: min[axis],
+            min: Vec2::new(min[axes[1]], min[axes[2]]),
+            max: Vec2::new(max[axes[1]], max[axes[2]]),
+            normal,
+            uv,
+            occlusion: sorted_occlusion,
+        }
+    }
+
+    fn corner(&self, u: f32, v: f32) -> Vec3 {
+        let mut corner = Vec3::ZERO;
+        corner[self.axes[0]] = self.depth;
+        corner[self.axes[1]] = u;
+        corner[self.axes[2]] = v;
+        corner
+    }
+
+    pub fn add_to(self, buffers: &mut MeshBuffers) {
+        let corners = [
+            self.corner(self.min.x, self.min.y),
+            self.corner(self.max.x, self.min.y),
+            self.corner(self.max.x, self.max.y),
+            self.corner(self.min.x, self.max.y),
+        ];
+        let normal = [self.normal; 3];
+        // Split along the diagonal with less occlusion, so occlusion is
+        // blended the same way in both directions
+        let o = self.occlusion;
+        let [a, b, c, d] = match o[0] + o[2] > o[1] + o[3] {
+            true => [1, 2, 3, 0],
+            false => [0, 1, 2, 3],
+        };
+        let tri = |i: [usize; 3]| (i.map(|i| corners[i]), i.map(|i| o[i]));
+        for (position, occlusion) in [tri([a, b, c]), tri([c, d, a])] {
+            buffers.add_tri(position, normal, self.uv, occlusion);
+        }
+    }
+
+    /// Whether every corner has the same occlusion, so the quad can be
+    /// stretched without changing how it is shaded
+    fn evenly_occluded(&self) -> bool {
+        self.occlusion.iter().all(|&o| o == self.occlusion[0])
+    }
+
+    /// Orders quads so the ones in the same plane with the same color and
+    /// occlusion are next to each other
+    fn plane_cmp(&self, other: &Self) -> Ordering {
+        self.axes
+            .cmp(&other.axes)
+            .then(self.depth.total_cmp(&other.depth))
+            .then(self.uv[0].total_cmp(&other.uv[0]))
+            .then(self.uv[1].total_cmp(&other.uv[1]))
+            .then(self.occlusion[0].total_cmp(&other.occlusion[0]))
+    }
+}
+
+/// Merges quads that share a whole side and have the same plane, color and
+/// even occlusion, until no more can be merged
+pub(super) fn merge_quads(quads: &mut Vec<Quad>) {
+    loop {
+        let len = quads.len();
+        merge_along(quads, 0);
+        merge_along(quads, 1);
+        if quads.len() == len {
+            return;
+        }
+    }
+}
+
+/// Merges quads next to each other along `dir` that have the same extent
+/// across it
+fn merge_along(quads: &mut Vec<Quad>, dir: usize) {
+    let across = 1 - dir;
+    quads.sort_by(|a, b| {
+        a.plane_cmp(b)
+            .then(a.min[across].total_cmp(&b.min[across]))
+            .then(a.max[across].total_cmp(&b.max[across]))
+            .then(a.min[dir].total_cmp(&b.min[dir]))
+    });
+    quads.dedup_by(|next, prev| {
+        let mergeable = prev.plane_cmp(next).is_eq()
+            && prev.evenly_occluded()
+            && next.evenly_occluded()
+            && prev.min[across] == next.min[across]
+            && prev.max[across] == next.max[across]
+            && prev.max[dir] == next.min[dir];
+        if mergeable {
+            prev.max[dir] = next.max[dir];
+        }
+        mergeable
+    });
+}
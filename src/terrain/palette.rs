@@ -0,0 +1,317 @@
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::TextureFormat,
+    utils::{BoxedFuture, HashMap},
+};
+use bevy_egui::egui;
+
+use super::tile::color::IndexedColor;
+
+/// Palette used until another is picked
+pub const DEFAULT_PALETTE: &str = "palettes/test.palette";
+/// Folder searched for palettes to pick from
+const PALETTE_FOLDER: &str = "palettes";
+/// Most recently used colors remembered by color pickers
+const MAX_RECENT_COLORS: usize = 8;
+
+pub struct PalettePlugin;
+
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Palette>()
+            .init_asset_loader::<PaletteLoader>()
+            .init_resource::<Swatches>()
+            .add_startup_system(load_palettes_system)
+            .add_system(update_swatches_system);
+    }
+}
+
+/// Colors tiles can use, read from a `.palette` file next to the image
+/// holding the colors
+///
+/// Palette file layout, one `key = value` per line, `#` starts a comment:
+/// - `name`: shown in the palette picker
+/// - `image`: path of the image relative to the palette file
+/// - `columns`: colors in each row of the image
+/// - `colors`: number of colors, read left to right then top to bottom
+/// - `color <index>` (optional): name of a color
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "9b8f0f64-3c9e-4b7a-a3f1-2a61c1d2e5f0"]
+pub struct Palette {
+    pub name: String,
+    pub texture: Handle<Image>,
+    pub columns: u8,
+    pub len: u8,
+    color_names: HashMap<u8, String>,
+}
+
+impl Palette {
+    pub fn colors(&self) -> impl Iterator<Item = IndexedColor> {
+        (0..self.len).map_while(IndexedColor::from_index)
+    }
+
+    pub fn color_name(&self, color: IndexedColor) -> Option<&str> {
+        self.color_names.get(&color.index()).map(String::as_str)
+    }
+
+    /// Position of `color` in the grid of the image
+    pub fn grid_pos(&self, color: IndexedColor) -> [u8; 2] {
+        [color.index() % self.columns, color.index() / self.columns]
+    }
+
+    /// Reads everything but the image, returning its path alongside the
+    /// palette
+    fn parse(text: &str) -> Result<(Self, String), String> {
+        let mut name = None;
+        let mut image = None;
+        let mut columns = None;
+        let mut len = None;
+        let mut color_names = HashMap::default();
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |msg: &str| format!("line {}: {msg}", line_index + 1);
+            let Some((key, value)) = line.split_once('=') else { return Err(error("expected `key = value`")) };
+            let (key, value) = (key.trim(), value.trim().to_owned());
+            let parse_u8 = |value: &str| value.parse::<u8>().map_err(|e| error(&e.to_string()));
+
+            match key.split_whitespace().collect::<Vec<_>>()[..] {
+                ["name"] => name = Some(value),
+                ["image"] => image = Some(value),
+                ["columns"] => columns = Some(parse_u8(&value)?),
+                ["colors"] => len = Some(parse_u8(&value)?),
+                ["color", index] => {
+                    color_names.insert(parse_u8(index)?, value);
+                }
+                _ => return Err(error(&format!("unknown key `{key}`"))),
+            }
+        }
+
+        let missing = |key| format!("missing `{key}`");
+        let palette = Self {
+            name: name.ok_or_else(|| missing("name"))?,
+            texture: Handle::default(),
+            columns: columns.ok_or_else(|| missing("columns"))?,
+            len: len.ok_or_else(|| missing("colors"))?,
+            color_names,
+        };
+        if palette.columns == 0 {
+            return Err("`columns` must not be 0".to_owned());
+        }
+        if palette.len == 0 {
+            return Err("`colors` must not be 0".to_owned());
+        }
+        if let Some(index) = palette.color_names.keys().find(|&&i| i >= palette.len) {
+            return Err(format!("named color {index} is not in the palette"));
+        }
+        Ok((palette, image.ok_or_else(|| missing("image"))?))
+    }
+}
+
+#[derive(Debug, Default)]
+struct PaletteLoader;
+
+impl AssetLoader for PaletteLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let (mut palette, image) = Palette::parse(std::str::from_utf8(bytes)?)
+                .map_err(|e| bevy::asset::Error::msg(format!("invalid palette: {e}")))?;
+            let image_path = AssetPath::new(load_context.path().with_file_name(image), None);
+            palette.texture = load_context.get_handle(image_path.clone());
+            load_context.set_default_asset(LoadedAsset::new(palette).with_dependency(image_path));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["palette"]
+    }
+}
+
+/// Every palette that can be picked and the one tiles are drawn with
+#[derive(Debug, Resource)]
+pub struct Palettes {
+    /// Keeps every file in the palette folder loaded
+    folder: Vec<HandleUntyped>,
+    pub current: Handle<Palette>,
+}
+
+impl Palettes {
+    /// Loaded palettes, sorted by name
+    pub fn loaded<'a>(&self, palettes: &'a Assets<Palette>) -> Vec<(Handle<Palette>, &'a Palette)> {
+        let mut loaded = self
+            .folder
+            .iter()
+            .filter_map(|handle| {
+                let handle = handle.clone().typed::<Palette>();
+                palettes.get(&handle).map(|palette| (handle, palette))
+            })
+            .collect::<Vec<_>>();
+        loaded.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+        loaded
+    }
+}
+
+fn load_palettes_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let folder = asset_server
+        .load_folder(PALETTE_FOLDER)
+        .unwrap_or_else(|e| {
+            error!("Failed to load palettes: {e}");
+            Vec::new()
+        })
+        .into_iter()
+        .filter(|handle| {
+            asset_server.get_handle_path(handle).map_or(false, |path| {
+                path.path()
+                    .extension()
+                    .map_or(false, |ext| ext == "palette")
+            })
+        })
+        .collect();
+    commands.insert_resource(Palettes {
+        folder,
+        current: asset_server.load(DEFAULT_PALETTE),
+    });
+}
+
+/// Colors of the current palette as drawn by color pickers
+#[derive(Debug, Default, Resource)]
+pub struct Swatches {
+    palette: Option<Palette>,
+    colors: Vec<egui::Color32>,
+    recent: Vec<IndexedColor>,
+}
+
+impl Swatches {
+    pub fn palette(&self) -> Option<&Palette> {
+        self.palette.as_ref()
+    }
+
+    pub fn color(&self, color: IndexedColor) -> Option<egui::Color32> {
+        self.colors.get(color.index() as usize).copied()
+    }
+
+    /// Most recently used first
+    pub fn recent(&self) -> &[IndexedColor] {
+        &self.recent
+    }
+
+    pub fn use_color(&mut self, color: IndexedColor) {
+        self.recent.retain(|&c| c != color);
+        self.recent.insert(0, color);
+        self.recent.truncate(MAX_RECENT_COLORS);
+    }
+
+    /// Reads the color of every cell of the palette grid, returning `None` if
+    /// the image format is not supported
+    fn read_colors(palette: &Palette, image: &Image) -> Option<Vec<egui::Color32>> {
+        if !matches!(
+            image.texture_descriptor.format,
+            TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm
+        ) {
+            return None;
+        }
+        let width = image.texture_descriptor.size.width as usize;
+        palette
+            .colors()
+            .map(|color| {
+                let [x, y] = palette.grid_pos(color).map(usize::from);
+                let i = (y * width + x) * 4;
+                let [r, g, b, a] = image.data.get(i..i + 4)?.try_into().unwrap();
+                Some(egui::Color32::from_rgba_unmultiplied(r, g, b, a))
+            })
+            .collect()
+    }
+}
+
+fn update_swatches_system(
+    palettes: Res<Palettes>,
+    palette_assets: Res<Assets<Palette>>,
+    images: Res<Assets<Image>>,
+    mut palette_events: EventReader<AssetEvent<Palette>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut swatches: ResMut<Swatches>,
+) {
+    let palette_changed = palette_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            *handle == palettes.current
+        }
+        AssetEvent::Removed { .. } => false,
+    });
+    let Some(palette) = palette_assets.get(&palettes.current) else { return };
+    // The image may finish loading after the palette
+    let image_changed = image_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            *handle == palette.texture
+        }
+        AssetEvent::Removed { .. } => false,
+    });
+    if !(palettes.is_changed() || palette_changed || image_changed) {
+        return;
+    }
+    let Some(image) = images.get(&palette.texture) else { return };
+
+    match Swatches::read_colors(palette, image) {
+        Some(colors) => swatches.colors = colors,
+        None => {
+            error!(
+                "Palette {} uses unsupported format {:?}",
+                palette.name, image.texture_descriptor.format
+            );
+            swatches.colors.clear();
+        }
+    }
+    swatches.palette = Some(palette.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::terrain::tile::color::IndexedColor;
+
+    use super::Palette;
+
+    #[test]
+    fn parse_palette() {
+        let (palette, image) = Palette::parse(
+            "# Comment\nname = Test Palette\nimage = test.png\ncolumns = 8\ncolors = 62\n\
+             color 0 = Red # Comment\ncolor 7 = Light Blue\n",
+        )
+        .unwrap();
+        assert_eq!(image, "test.png");
+        assert_eq!(palette.name, "Test Palette");
+        assert_eq!(palette.colors().count(), 62);
+        let light_blue = IndexedColor::from_index(7).unwrap();
+        assert_eq!(palette.color_name(light_blue), Some("Light Blue"));
+        assert_eq!(palette.color_name(IndexedColor::DEFAULT), Some("Red"));
+        assert_eq!(
+            palette.grid_pos(IndexedColor::from_index(13).unwrap()),
+            [5, 1]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_palette() {
+        let valid = "name = A\nimage = a.png\ncolumns = 4\ncolors = 16\n";
+        assert!(Palette::parse(valid).is_ok());
+        for invalid in [
+            "name = A\nimage = a.png\ncolumns = 4\n",
+            "name = A\nimage = a.png\ncolumns = 0\ncolors = 16\n",
+            "name = A\nimage = a.png\ncolumns = 4\ncolors = 0\n",
+            "name = A\nimage = a.png\ncolumns = 4\ncolors = 256\n",
+            "name = A\nimage = a.png\ncolumns = 4\ncolors = 16\ncolor 16 = B\n",
+            "name = A\nimage = a.png\ncolumns = 4\ncolors = 16\nsize = 2\n",
+            "name = A\nimage = a.png\ncolumns = 4\ncolors = 16\nnot a pair\n",
+        ] {
+            assert!(Palette::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+}
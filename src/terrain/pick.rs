@@ -0,0 +1,206 @@
+use bevy::{prelude::*, window::CursorGrabMode};
+use bevy_egui::EguiContext;
+use bevy_flycam::FlyCam;
+
+use super::{history::History, tile::Tile, GlobalPos, Terrain};
+
+/// Furthest away a tile can be picked from
+const PICK_DISTANCE: f32 = 128.0;
+
+pub struct PickPlugin;
+
+impl Plugin for PickPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SelectedTile>()
+            .add_system(pick_tile_system);
+    }
+}
+
+/// The tile placed when clicking in the world
+#[derive(Debug, Default, Clone, Copy, Deref, DerefMut, Resource)]
+pub struct SelectedTile(pub Tile);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    pub pos: GlobalPos,
+    /// Normal of the face that was hit, or zero if the ray started inside a
+    /// tile
+    pub normal: IVec3,
+    pub distance: f32,
+}
+
+impl RaycastHit {
+    /// The position in front of the face that was hit
+    pub fn adjacent(self) -> GlobalPos {
+        self.pos.offset(self.normal)
+    }
+}
+
+impl Terrain {
+    /// Finds the first tile along a ray by stepping through every tile it
+    /// crosses
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit> {
+        let direction = direction.try_normalize()?;
+        // Shift so every tile fills the cube from its position to its position + 1
+        let origin = origin + Vec3::ONE;
+        let mut pos = origin.floor().as_ivec3();
+        let step = direction.to_array().map(|d| match d {
+            d if d > 0.0 => 1,
+            d if d < 0.0 => -1,
+            _ => 0,
+        });
+        let t_delta = direction.to_array().map(|d| (1.0 / d).abs());
+        let mut t_max = [0, 1, 2].map(|i| match step[i] {
+            1 => (pos[i] as f32 + 1.0 - origin[i]) * t_delta[i],
+            -1 => (origin[i] - pos[i] as f32) * t_delta[i],
+            _ => f32::INFINITY,
+        });
+        let mut normal = IVec3::ZERO;
+        let mut distance = 0.0;
+
+        loop {
+            let global_pos = GlobalPos::from_xyz_i32(pos);
+            if self.get(global_pos).is_some() {
+                return Some(RaycastHit {
+                    pos: global_pos,
+                    normal,
+                    distance,
+                });
+            }
+
+            let axis = (0..3)
+                .min_by(|&a, &b| t_max[a].total_cmp(&t_max[b]))
+                .unwrap();
+            distance = t_max[axis];
+            if distance > max_distance {
+                return None;
+            }
+            pos[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+    }
+}
+
+fn pick_tile_system(
+    mut egui_context: ResMut<EguiContext>,
+    windows: Res<Windows>,
+    mouse: Res<Input<MouseButton>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<FlyCam>>,
+    selected: Res<SelectedTile>,
+    mut terrain: ResMut<Terrain>,
+    mut history: ResMut<History>,
+) {
+    let place = mouse.just_pressed(MouseButton::Left);
+    let remove = mouse.just_pressed(MouseButton::Right);
+    if !(place || remove) || egui_context.ctx_mut().wants_pointer_input() {
+        return;
+    }
+    let Some(window) = windows.get_primary() else { return };
+    let Ok((camera, camera_transform)) = cameras.get_single() else { return };
+
+    // While the camera is being flown the cursor is locked, so pick from the
+    // center of the screen instead
+    let center = Vec2::new(window.width(), window.height()) / 2.0;
+    let cursor = match window.cursor_grab_mode() {
+        CursorGrabMode::None => window.cursor_position().unwrap_or(center),
+        _ => center,
+    };
+    let Some(ray) = camera.viewport_to_world(camera_transform, cursor) else { return };
+    let Some(hit) = terrain.raycast(ray.origin, ray.direction, PICK_DISTANCE) else { return };
+
+    if place && hit.normal != IVec3::ZERO {
+        history.record(
+            &mut terrain,
+            format!("Place {}", hit.adjacent()),
+            |terrain| terrain.set(hit.adjacent(), selected.0),
+        );
+    } else if remove {
+        history.record(&mut terrain, format!("Remove {}", hit.pos), |terrain| {
+            terrain.remove(hit.pos)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use proptest::prelude::*;
+
+    use crate::terrain::{tile::Tile, GlobalPos, Terrain};
+
+    const AXES: [IVec3; 6] = [
+        IVec3::X,
+        IVec3::NEG_X,
+        IVec3::Y,
+        IVec3::NEG_Y,
+        IVec3::Z,
+        IVec3::NEG_Z,
+    ];
+
+    proptest! {
+        #[test]
+        fn raycast_hits_tile(
+            xyz in [-64..64, -64..64, -64..64],
+            axis in 0..6_usize,
+            distance in 1.0..32.0_f32,
+            offset in [-0.45..0.45_f32, -0.45..0.45_f32, -0.45..0.45_f32],
+        ) {
+            let pos = GlobalPos::from_xyz_i32(xyz);
+            let mut terrain = Terrain::default();
+            terrain.set(pos, Tile::BRICK);
+
+            let normal = AXES[axis];
+            let center = pos.center();
+            let offset = Vec3::from(offset) * (Vec3::ONE - normal.abs().as_vec3());
+            let origin = center + offset + normal.as_vec3() * (distance + 0.5);
+
+            let hit = terrain.raycast(origin, -normal.as_vec3(), 64.0).unwrap();
+            assert_eq!(hit.pos, pos);
+            assert_eq!(hit.normal, normal);
+            assert_eq!(hit.adjacent(), GlobalPos::from_xyz_i32(IVec3::from(xyz) + normal));
+            assert!((hit.distance - distance).abs() < 0.01);
+
+            assert_eq!(terrain.raycast(origin, normal.as_vec3(), 64.0), None);
+            assert_eq!(terrain.raycast(origin, -normal.as_vec3(), distance - 0.1), None);
+        }
+
+        #[test]
+        fn raycast_starts_inside_tile(xyz in [-64..64, -64..64, -64..64], direction: [f32; 3]) {
+            let pos = GlobalPos::from_xyz_i32(xyz);
+            let mut terrain = Terrain::default();
+            terrain.set(pos, Tile::BRICK);
+
+            let center = pos.center();
+            if let Some(hit) = terrain.raycast(center, Vec3::from(direction), 64.0) {
+                assert_eq!(hit.pos, pos);
+                assert_eq!(hit.normal, IVec3::ZERO);
+            }
+        }
+    }
+
+    #[test]
+    fn raycast_hits_nearest_tile() {
+        let mut terrain = Terrain::default();
+        for x in [3, 5, 9] {
+            terrain.set(GlobalPos::from_xyz_i32([x, 0, 0]), Tile::BRICK);
+        }
+
+        let hit = terrain
+            .raycast(Vec3::new(-10.0, -0.5, -0.5), Vec3::X, 64.0)
+            .unwrap();
+        assert_eq!(hit.pos, GlobalPos::from_xyz_i32([3, 0, 0]));
+        assert_eq!(hit.normal, IVec3::NEG_X);
+    }
+
+    #[test]
+    fn raycast_misses_empty_terrain() {
+        let terrain = Terrain::default();
+        assert_eq!(
+            terrain.raycast(Vec3::ZERO, Vec3::new(1.0, 2.0, 3.0), 64.0),
+            None
+        );
+        assert_eq!(terrain.raycast(Vec3::ZERO, Vec3::ZERO, 64.0), None);
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use bevy::utils::HashMap;
+
+use super::{
+    chunk::{Chunk, ChunkPos, LocalPos, TileSlot},
+    tile::{color::IndexedColor, orientation::Orientation, shape::Shape, Tile},
+    Terrain,
+};
+
+/// Where the world is saved to when no other path is given
+pub const DEFAULT_WORLD_PATH: &str = "worlds/world.vcw";
+
+/// First bytes of every world file
+const MAGIC: [u8; 4] = *b"VCTW";
+/// Current version of the world file format
+///
+/// Layout (all integers little endian):
+/// - `MAGIC`
+/// - version: `u16`
+/// - chunk count: `u32`
+/// - for each chunk:
+///   - position: 3 x `i32`
+///   - every tile slot in `LocalPos` bit order (see `write_slot`)
+const VERSION: u16 = 4;
+/// Oldest version of the world file format that can still be loaded.  Version
+/// 1 only had brick tiles, tiles before version 3 had no orientation, and tiles
+/// before version 4 had no shape
+const MIN_VERSION: u16 = 1;
+
+impl Terrain {
+    pub fn save(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.chunks.len() as u32).to_le_bytes())?;
+        for (pos, chunk) in &self.chunks {
+            for v in pos.to_array() {
+                writer.write_all(&v.to_le_bytes())?;
+            }
+            for local in LocalPos::positions() {
+                write_slot(&mut writer, chunk[local])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces all tiles with the ones read from `reader`.  If reading fails
+    /// self is left unchanged
+    pub fn load(&mut self, mut reader: impl Read) -> io::Result<()> {
+        if read_array(&mut reader)? != MAGIC {
+            return Err(invalid_data("not a world file"));
+        }
+        let version = u16::from_le_bytes(read_array(&mut reader)?);
+        if !(MIN_VERSION..=VERSION).contains(&version) {
+            return Err(invalid_data(format!(
+                "unsupported world file version {version}"
+            )));
+        }
+
+        let chunk_count = u32::from_le_bytes(read_array(&mut reader)?);
+        let mut chunks = HashMap::default();
+        for _ in 0..chunk_count {
+            let pos = ChunkPos::new(
+                i32::from_le_bytes(read_array(&mut reader)?),
+                i32::from_le_bytes(read_array(&mut reader)?),
+                i32::from_le_bytes(read_array(&mut reader)?),
+            );
+            let mut chunk = Chunk::default();
+            for local in LocalPos::positions() {
+                if let Some(tile) = read_slot(&mut reader, version)? {
+                    let _ = chunk.set(local, tile);
+                }
+            }
+            if chunk.is_empty() {
+                continue;
+            }
+            if chunks.insert(pos, chunk).is_some() {
+                return Err(invalid_data(format!(
+                    "chunk {:?} appears more than once",
+                    pos.to_array()
+                )));
+            }
+        }
+
+        self.clear();
+        for &pos in chunks.keys() {
+            self.mark_chunk_changed(pos);
+        }
+        self.chunks = chunks;
+        Ok(())
+    }
+
+    pub fn save_to_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.save(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load_from_file(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.load(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Tile slot layout: kind: `u8`, then kind specific data
+/// - 0: air
+/// - 1: brick, color: `u8`, orientation: `u8`, shape: `u8`
+/// - 2: concrete, color: `u8`, orientation: `u8`, shape: `u8`
+/// - 3: glass, color: `u8`, orientation: `u8`, shape: `u8`
+/// - 4: water, color: `u8`, orientation: `u8`, shape: `u8`
+/// - 5: foliage, color: `u8`, orientation: `u8`, shape: `u8`
+fn write_slot(writer: &mut impl Write, slot: TileSlot) -> io::Result<()> {
+    let Some(tile) = slot else { return writer.write_all(&[0]) };
+    let kind = match tile {
+        Tile::Brick { .. } => 1,
+        Tile::Concrete { .. } => 2,
+        Tile::Glass { .. } => 3,
+        Tile::Water { .. } => 4,
+        Tile::Foliage { .. } => 5,
+    };
+    writer.write_all(&[
+        kind,
+        tile.color().index(),
+        tile.orientation().index(),
+        tile.shape().index(),
+    ])
+}
+
+/// Reads a tile slot written by `write_slot` in the world file format
+/// `version`
+fn read_slot(reader: &mut impl Read, version: u16) -> io::Result<TileSlot> {
+    let [kind] = read_array(reader)?;
+    match kind {
+        0 => Ok(None),
+        1..=5 => {
+            let mut tile = match kind {
+                1 => Tile::BRICK,
+                2 => Tile::CONCRETE,
+                3 => Tile::GLASS,
+                4 => Tile::WATER,
+                _ => Tile::FOLIAGE,
+            };
+            *tile.color_mut() = read_color(reader)?;
+            if version >= 3 {
+                *tile.orientation_mut() = read_orientation(reader)?;
+            }
+            if version >= 4 {
+                *tile.shape_mut() = read_shape(reader)?;
+            }
+            Ok(Some(tile))
+        }
+        _ => Err(invalid_data(format!("unknown tile kind {kind}"))),
+    }
+}
+
+fn read_color(reader: &mut impl Read) -> io::Result<IndexedColor> {
+    let [index] = read_array(reader)?;
+    IndexedColor::from_index(index).ok_or_else(|| invalid_data(format!("invalid color {index}")))
+}
+
+fn read_orientation(reader: &mut impl Read) -> io::Result<Orientation> {
+    let [index] = read_array(reader)?;
+    Orientation::from_index(index)
+        .ok_or_else(|| invalid_data(format!("invalid orientation {index}")))
+}
+
+fn read_shape(reader: &mut impl Read) -> io::Result<Shape> {
+    let [index] = read_array(reader)?;
+    Shape::from_index(index).ok_or_else(|| invalid_data(format!("invalid shape {index}")))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::terrain::{
+        tests::arb_global_pos,
+        tile::{
+            color::IndexedColor, orientation::tests::arb_orientation, shape::tests::arb_shape, Tile,
+        },
+        GlobalPos, Terrain,
+    };
+
+    use super::MAGIC;
+
+    prop_compose! {
+        fn arb_tile()(
+            kind in 0..Tile::KINDS.len(),
+            index in 0..=IndexedColor::MAX_INDEX,
+            orientation in arb_orientation(),
+            shape in arb_shape(),
+        ) -> Tile {
+            let mut tile = Tile::KINDS[kind];
+            *tile.color_mut() = IndexedColor::from_index(index).unwrap();
+            *tile.orientation_mut() = orientation;
+            *tile.shape_mut() = shape;
+            tile
+        }
+    }
+
+    #[test]
+    fn load_version_1() {
+        // One chunk at [1, -2, 3] with brick of color 7 at its start, and air
+        // everywhere else
+        let mut bytes = Vec::from(MAGIC);
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        for v in [1i32, -2, 3] {
+            bytes.extend(v.to_le_bytes());
+        }
+        bytes.extend([1, 7]);
+        bytes.extend([0; 4095]);
+
+        let mut terrain = Terrain::default();
+        terrain.load(bytes.as_slice()).unwrap();
+        let tile = terrain.get(GlobalPos::from_xyz_i32([16, -32, 48]));
+        let mut brick = Tile::BRICK;
+        *brick.color_mut() = IndexedColor::from_index(7).unwrap();
+        assert_eq!(tile, Some(brick));
+    }
+
+    #[test]
+    fn load_version_2() {
+        // One chunk at [1, -2, 3] with concrete of color 7 at its start, and
+        // air everywhere else
+        let mut bytes = Vec::from(MAGIC);
+        bytes.extend(2u16.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        for v in [1i32, -2, 3] {
+            bytes.extend(v.to_le_bytes());
+        }
+        bytes.extend([2, 7]);
+        bytes.extend([0; 4095]);
+
+        let mut terrain = Terrain::default();
+        terrain.load(bytes.as_slice()).unwrap();
+        let tile = terrain.get(GlobalPos::from_xyz_i32([16, -32, 48]));
+        let mut concrete = Tile::CONCRETE;
+        *concrete.color_mut() = IndexedColor::from_index(7).unwrap();
+        assert_eq!(tile, Some(concrete));
+    }
+
+    proptest! {
+        #[test]
+        fn save_load_terrain(
+            tiles in proptest::collection::vec((arb_global_pos(), arb_tile()), 1..256)
+        ) {
+            let mut terrain = Terrain::default();
+            for &(pos, tile) in &tiles {
+                terrain.set(pos, tile);
+            }
+
+            let mut bytes = Vec::new();
+            terrain.save(&mut bytes).unwrap();
+            let mut loaded = Terrain::default();
+            loaded.load(bytes.as_slice()).unwrap();
+
+            assert_eq!(loaded.chunks, terrain.chunks);
+            for pos in loaded.chunks.keys() {
+                assert!(loaded.changed.contains(pos));
+            }
+        }
+
+        #[test]
+        fn load_truncated_terrain(
+            tiles in proptest::collection::vec((arb_global_pos(), arb_tile()), 1..16),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let mut terrain = Terrain::default();
+            for &(pos, tile) in &tiles {
+                terrain.set(pos, tile);
+            }
+
+            let mut bytes = Vec::new();
+            terrain.save(&mut bytes).unwrap();
+            let cut = cut.index(bytes.len());
+
+            let mut loaded = Terrain::default();
+            loaded.set(tiles[0].0, tiles[0].1);
+            assert!(loaded.load(&bytes[..cut]).is_err());
+            assert_eq!(loaded.get(tiles[0].0), Some(tiles[0].1));
+        }
+    }
+}
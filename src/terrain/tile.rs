@@ -1,14 +1,43 @@
+use std::mem;
+
+use bevy::prelude::*;
 use derive_more::IsVariant;
 
-use self::color::IndexedColor;
+use self::{color::IndexedColor, orientation::Orientation, shape::Shape};
 
 pub mod color;
 mod inspect;
 mod mesh;
+pub mod orientation;
+pub mod shape;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, IsVariant)]
 pub enum Tile {
-    Brick { color: IndexedColor },
+    Brick {
+        color: IndexedColor,
+        orientation: Orientation,
+        shape: Shape,
+    },
+    Concrete {
+        color: IndexedColor,
+        orientation: Orientation,
+        shape: Shape,
+    },
+    Glass {
+        color: IndexedColor,
+        orientation: Orientation,
+        shape: Shape,
+    },
+    Water {
+        color: IndexedColor,
+        orientation: Orientation,
+        shape: Shape,
+    },
+    Foliage {
+        color: IndexedColor,
+        orientation: Orientation,
+        shape: Shape,
+    },
 }
 
 impl Default for Tile {
@@ -20,11 +49,215 @@ impl Default for Tile {
 impl Tile {
     pub const BRICK: Self = Self::Brick {
         color: IndexedColor::DEFAULT,
+        orientation: Orientation::DEFAULT,
+        shape: Shape::Cube,
+    };
+    pub const CONCRETE: Self = Self::Concrete {
+        color: IndexedColor::new(32),
+        orientation: Orientation::DEFAULT,
+        shape: Shape::Cube,
+    };
+    pub const GLASS: Self = Self::Glass {
+        color: IndexedColor::new(45),
+        orientation: Orientation::DEFAULT,
+        shape: Shape::Cube,
     };
+    pub const WATER: Self = Self::Water {
+        color: IndexedColor::new(4),
+        orientation: Orientation::DEFAULT,
+        shape: Shape::Cube,
+    };
+    pub const FOLIAGE: Self = Self::Foliage {
+        color: IndexedColor::new(3),
+        orientation: Orientation::DEFAULT,
+        shape: Shape::Cube,
+    };
+
+    /// The default tile of every kind
+    pub const KINDS: [Self; 5] = [
+        Self::BRICK,
+        Self::CONCRETE,
+        Self::GLASS,
+        Self::WATER,
+        Self::FOLIAGE,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tile::Brick { .. } => "Brick",
+            Tile::Concrete { .. } => "Concrete",
+            Tile::Glass { .. } => "Glass",
+            Tile::Water { .. } => "Water",
+            Tile::Foliage { .. } => "Foliage",
+        }
+    }
+
+    pub fn color(self) -> IndexedColor {
+        match self {
+            Tile::Brick { color, .. }
+            | Tile::Concrete { color, .. }
+            | Tile::Glass { color, .. }
+            | Tile::Water { color, .. }
+            | Tile::Foliage { color, .. } => color,
+        }
+    }
+
+    pub fn color_mut(&mut self) -> &mut IndexedColor {
+        match self {
+            Tile::Brick { color, .. }
+            | Tile::Concrete { color, .. }
+            | Tile::Glass { color, .. }
+            | Tile::Water { color, .. }
+            | Tile::Foliage { color, .. } => color,
+        }
+    }
+
+    pub fn orientation(self) -> Orientation {
+        match self {
+            Tile::Brick { orientation, .. }
+            | Tile::Concrete { orientation, .. }
+            | Tile::Glass { orientation, .. }
+            | Tile::Water { orientation, .. }
+            | Tile::Foliage { orientation, .. } => orientation,
+        }
+    }
+
+    pub fn orientation_mut(&mut self) -> &mut Orientation {
+        match self {
+            Tile::Brick { orientation, .. }
+            | Tile::Concrete { orientation, .. }
+            | Tile::Glass { orientation, .. }
+            | Tile::Water { orientation, .. }
+            | Tile::Foliage { orientation, .. } => orientation,
+        }
+    }
+
+    pub fn shape(self) -> Shape {
+        match self {
+            Tile::Brick { shape, .. }
+            | Tile::Concrete { shape, .. }
+            | Tile::Glass { shape, .. }
+            | Tile::Water { shape, .. }
+            | Tile::Foliage { shape, .. } => shape,
+        }
+    }
+
+    pub fn shape_mut(&mut self) -> &mut Shape {
+        match self {
+            Tile::Brick { shape, .. }
+            | Tile::Concrete { shape, .. }
+            | Tile::Glass { shape, .. }
+            | Tile::Water { shape, .. }
+            | Tile::Foliage { shape, .. } => shape,
+        }
+    }
+
+    /// The tile turned a quarter turn counterclockwise around `axis`, looking
+    /// from where it points
+    pub fn rotated(mut self, axis: IVec3) -> Self {
+        *self.orientation_mut() = self.orientation().rotated(axis);
+        self
+    }
+
+    /// Whether `self` and `other` are the same kind of tile, ignoring color,
+    /// orientation and shape
+    pub fn same_kind(self, other: Tile) -> bool {
+        mem::discriminant(&self) == mem::discriminant(&other)
+    }
 
-    /// Solid tiles completely hide faces of adjacent tiles that face them,
-    /// meaning rendering those faces can be skipped
+    /// Whether nothing can be seen through the part of the tile its shape
+    /// fills
+    pub fn is_opaque(self) -> bool {
+        matches!(self, Tile::Brick { .. } | Tile::Concrete { .. })
+    }
+
+    /// Solid tiles are opaque cubes, so they completely hide faces of
+    /// adjacent tiles that face them, meaning rendering those faces can be
+    /// skipped
     pub fn is_solid(tile: Option<Tile>) -> bool {
-        tile.is_some()
+        matches!(tile, Some(tile) if tile.is_opaque() && tile.shape() == Shape::Cube)
+    }
+
+    /// Whether `tile` fills `point`, relative to the center of its tile.
+    /// Tiles that are not cubes only hide the parts of faces they fill
+    pub fn fills(tile: Option<Tile>, point: Vec3) -> bool {
+        matches!(tile, Some(tile) if tile.shape().contains(tile.orientation().unapply(point)))
+    }
+
+    /// Whether `neighbour` hides the parts of the face of self that touch it
+    /// which it fills.  Glass, water and foliage can be seen through or
+    /// around, so they only hide faces of their own kind and a mass of them
+    /// is only meshed on the outside
+    pub fn is_hidden_by(self, neighbour: Option<Tile>) -> bool {
+        match neighbour {
+            Some(neighbour) if neighbour.is_opaque() => true,
+            Some(neighbour @ (Tile::Glass { .. } | Tile::Water { .. } | Tile::Foliage { .. })) => {
+                self.same_kind(neighbour)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use proptest::prelude::*;
+
+    use super::{orientation::tests::arb_orientation, shape::Shape, Tile};
+
+    #[test]
+    fn solid_tiles_hide_everything() {
+        for tile in Tile::KINDS {
+            assert!(tile.is_hidden_by(Some(Tile::BRICK)));
+            assert!(tile.is_hidden_by(Some(Tile::CONCRETE)));
+            assert!(!tile.is_hidden_by(None));
+        }
+    }
+
+    #[test]
+    fn transparent_tiles_hide_own_kind() {
+        assert!(Tile::GLASS.is_hidden_by(Some(Tile::GLASS)));
+        assert!(Tile::WATER.is_hidden_by(Some(Tile::WATER)));
+        assert!(!Tile::BRICK.is_hidden_by(Some(Tile::GLASS)));
+        assert!(!Tile::WATER.is_hidden_by(Some(Tile::GLASS)));
+        assert!(!Tile::GLASS.is_hidden_by(Some(Tile::WATER)));
+        assert!(Tile::FOLIAGE.is_hidden_by(Some(Tile::FOLIAGE)));
+        assert!(!Tile::BRICK.is_hidden_by(Some(Tile::FOLIAGE)));
+        assert!(!Tile::GLASS.is_hidden_by(Some(Tile::FOLIAGE)));
+    }
+
+    #[test]
+    fn shaped_tiles_fill_part() {
+        let mut slope = Tile::CONCRETE;
+        *slope.shape_mut() = Shape::Slope;
+        assert!(!Tile::is_solid(Some(slope)));
+        assert!(Tile::CONCRETE.is_hidden_by(Some(slope)));
+
+        let point = Vec3::new(0.0, 0.125, 0.375);
+        assert!(Tile::fills(Some(Tile::CONCRETE), point));
+        assert!(!Tile::fills(Some(slope), point));
+        assert!(Tile::fills(Some(slope), -point));
+        // Turned around, the high side of the slope is at the front
+        let turned = slope.rotated(IVec3::Y).rotated(IVec3::Y);
+        assert!(Tile::fills(Some(turned), point));
+        assert!(!Tile::fills(None, point));
+    }
+
+    proptest! {
+        #[test]
+        fn rotate_tile_four_times(
+            kind in 0..Tile::KINDS.len(),
+            orientation in arb_orientation(),
+            axis in prop::sample::select(&[IVec3::X, IVec3::Y, IVec3::Z][..]),
+        ) {
+            let mut tile = Tile::KINDS[kind];
+            *tile.orientation_mut() = orientation;
+            let rotated = tile.rotated(axis);
+            prop_assert_ne!(rotated, tile);
+            prop_assert!(rotated.same_kind(tile));
+            prop_assert_eq!(rotated.color(), tile.color());
+            prop_assert_eq!(rotated.rotated(axis).rotated(axis).rotated(axis), tile);
+        }
     }
 }
@@ -2,6 +2,11 @@ use std::num::NonZeroU8;
 
 use bevy_egui::egui::{self, Widget};
 
+use crate::terrain::palette::Swatches;
+
+/// Size of each color in a color picker
+const SWATCH_SIZE: egui::Vec2 = egui::Vec2::new(14.0, 14.0);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct IndexedColor(NonZeroU8);
 
@@ -15,12 +20,27 @@ impl IndexedColor {
     // Safety: the value is not zero
     pub const DEFAULT: Self = Self(unsafe { NonZeroU8::new_unchecked(1) });
 
-    pub const MAX_INDEX: u8 = 61;
+    /// Largest index that fits in the save format, palettes may have fewer
+    /// colors
+    pub const MAX_INDEX: u8 = u8::MAX - 1;
+
+    /// Constructs a color in a const context
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is above `MAX_INDEX`
+    pub const fn new(i: u8) -> Self {
+        assert!(i <= Self::MAX_INDEX);
+        match NonZeroU8::new(i + 1) {
+            Some(v) => Self(v),
+            None => unreachable!(),
+        }
+    }
 
+    /// The index is passed to the shader through the uv, so meshes do not
+    /// depend on the layout of the palette
     pub fn uv(self) -> [f32; 2] {
-        let x = (self.index() % 8) as f32 + 0.5;
-        let y = (self.index() / 8) as f32 + 0.5;
-        [x / 8.0, y / 8.0]
+        [self.index() as f32, 0.0]
     }
 
     pub fn index(self) -> u8 {
@@ -32,6 +52,79 @@ impl IndexedColor {
     }
 }
 
+impl IndexedColor {
+    /// Grid of every color in the palette, laid out like the palette image.
+    /// Falls back to picking the index if the palette has not loaded
+    pub fn picker<'a>(&'a mut self, swatches: &'a mut Swatches) -> impl Widget + 'a {
+        move |ui: &mut egui::Ui| {
+            let Some(palette) = swatches.palette() else { return ui.add(self) };
+            let mut picked = None;
+            let mut response = ui
+                .vertical(|ui| {
+                    ui.spacing_mut().item_spacing = egui::Vec2::splat(2.0);
+                    let colors = palette.colors().collect::<Vec<_>>();
+                    for row in colors.chunks(palette.columns.into()) {
+                        ui.horizontal(|ui| {
+                            for &color in row {
+                                if swatch(ui, swatches, color, *self == color).clicked() {
+                                    picked = Some(color);
+                                }
+                            }
+                        });
+                    }
+
+                    if !swatches.recent().is_empty() {
+                        ui.label("Recent");
+                        ui.horizontal(|ui| {
+                            for &color in swatches.recent() {
+                                if swatch(ui, swatches, color, *self == color).clicked() {
+                                    picked = Some(color);
+                                }
+                            }
+                        });
+                    }
+                })
+                .response;
+
+            if let Some(color) = picked {
+                *self = color;
+                swatches.use_color(color);
+                response.mark_changed();
+            }
+            response
+        }
+    }
+}
+
+fn swatch(
+    ui: &mut egui::Ui,
+    swatches: &Swatches,
+    color: IndexedColor,
+    selected: bool,
+) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(SWATCH_SIZE, egui::Sense::click());
+    // Same as the terrain shader uses for missing colors
+    let fill = swatches
+        .color(color)
+        .unwrap_or(egui::Color32::from_rgb(255, 0, 255));
+    ui.painter().rect_filled(rect, 2.0, fill);
+    if selected {
+        ui.painter()
+            .rect_stroke(rect, 2.0, ui.visuals().selection.stroke);
+    } else if response.hovered() {
+        ui.painter()
+            .rect_stroke(rect, 2.0, ui.visuals().widgets.hovered.fg_stroke);
+    }
+
+    let name = swatches
+        .palette()
+        .and_then(|palette| palette.color_name(color));
+    response.on_hover_text(match name {
+        Some(name) => format!("{}: {name}", color.index()),
+        None => color.index().to_string(),
+    })
+}
+
 impl Widget for &mut IndexedColor {
     fn ui(self, ui: &mut egui::Ui) -> egui::Response {
         let mut index = self.index();
@@ -1,45 +1,55 @@
 use bevy_egui::egui::{self, Widget};
 
-use crate::terrain::chunk::TileSlot;
+use crate::terrain::{chunk::TileSlot, palette::Swatches};
 
 use super::Tile;
 
 impl Tile {
-    pub fn widget(&mut self) -> impl Widget + '_ {
+    pub fn widget<'a>(&'a mut self, swatches: &'a mut Swatches) -> impl Widget + 'a {
         move |ui: &mut egui::Ui| {
             ui.vertical_centered_justified(|ui| {
-                let brick = ui.selectable_label(self.is_brick(), "Brick");
-                if brick.clicked() && !self.is_brick() {
-                    *self = Tile::BRICK;
+                let mut changed = false;
+                for kind in Tile::KINDS {
+                    let label = ui.selectable_label(self.same_kind(kind), kind.name());
+                    if label.clicked() && !self.same_kind(kind) {
+                        *self = kind;
+                        changed = true;
+                    }
                 }
-                let mut response = brick;
 
-                match self {
-                    Tile::Brick { color } => response |= ui.add(color),
+                let mut response = ui.add(self.color_mut().picker(swatches));
+                response |= ui.add(self.shape_mut());
+                response |= ui.add(self.orientation_mut());
+                if changed {
+                    response.mark_changed();
                 }
-
                 response
             })
             .inner
         }
     }
 
-    pub fn widget_option(tile_slot: &mut TileSlot) -> impl Widget + '_ {
+    pub fn widget_option<'a>(
+        tile_slot: &'a mut TileSlot,
+        swatches: &'a mut Swatches,
+    ) -> impl Widget + 'a {
         move |ui: &mut egui::Ui| {
             ui.vertical_centered_justified(|ui| {
                 let mut response = ui.selectable_value(tile_slot, None, "Air");
                 if let Some(tile) = tile_slot {
-                    response |= ui.add(tile.widget());
+                    response |= ui.add(tile.widget(swatches));
                 } else {
                     // Must match `Tile::widget` or button becomes deselected
                     // after it is pressed
                     ui.vertical_centered_justified(|ui| {
-                        let brick = ui.selectable_label(false, "Brick");
-                        if brick.clicked() {
-                            *tile_slot = Some(Tile::BRICK);
-                            response.mark_changed();
+                        for kind in Tile::KINDS {
+                            let label = ui.selectable_label(false, kind.name());
+                            if label.clicked() {
+                                *tile_slot = Some(kind);
+                                response.mark_changed();
+                            }
+                            response |= label;
                         }
-                        response |= brick;
                     })
                     .inner
                 }
@@ -1,33 +1,202 @@
+use bevy::prelude::*;
+
 use crate::terrain::mesh::{
-    mesh_builder::{Face, MeshBuilder, SubtileFace, WallFlags},
+    mesh_builder::{
+        Edge, EdgeCap, Face, FaceParts, MeshBuilder, MeshLayer, Slope, Step, SubtileEdge,
+        SubtileFace, WallFlags,
+    },
     CornerTiles, Subtile,
 };
 
-use super::Tile;
+use super::{orientation::Orientation, shape::Shape, Tile};
+
+/// How far below the top of its tile the surface of water is
+const WATER_DEPTH: f32 = 0.125;
+/// How far from a side points checking whether the tiles on either side of it
+/// fill it are
+const SAMPLE_DEPTH: f32 = 0.01;
 
 impl Tile {
+    pub fn mesh_layer(self) -> MeshLayer {
+        match self {
+            Tile::Glass { .. } | Tile::Water { .. } => MeshLayer::Transparent,
+            _ => MeshLayer::Opaque,
+        }
+    }
+
+    /// Shape of the edges between exposed faces.  Only cubes have rounded or
+    /// beveled edges
+    pub fn edge(self) -> Edge {
+        match self {
+            _ if self.shape() != Shape::Cube => Edge::None,
+            Tile::Brick { .. } => Edge::Round,
+            Tile::Concrete { .. } => Edge::Bevel,
+            _ => Edge::None,
+        }
+    }
+
+    /// Whether walls get mortar where they meet a different tile
+    pub fn has_mortar(self) -> bool {
+        !matches!(self, Tile::Water { .. } | Tile::Foliage { .. })
+    }
+
+    /// Which sides of the wall facing along `face` at `subtile` continue into
+    /// the same tile.  Only the sides touching the corner are checked
+    fn connections(tiles: &CornerTiles, subtile: Subtile, face: SubtileFace) -> WallFlags {
+        let mut flags = WallFlags::CONNECTED_ALL;
+        for axis in [Subtile::X, Subtile::Y, Subtile::Z] {
+            if axis != face.subtile_axis() && tiles[subtile ^ axis] != tiles[subtile] {
+                flags -= WallFlags::side(face, -subtile.into_tile(axis));
+            }
+        }
+        flags
+    }
+
+    /// Whether the tile at `subtile` fills `point` of the corner
+    fn fills_at(tiles: &CornerTiles, subtile: Subtile, point: Vec3) -> bool {
+        Tile::fills(tiles[subtile], point - subtile.offset() * 0.5)
+    }
+
+    /// Parts of the face of the tile at `subtile` facing along `face` that it
+    /// fills and the tile it faces does not hide
+    fn visible_parts(tiles: &CornerTiles, subtile: Subtile, face: SubtileFace) -> FaceParts {
+        let Some(tile) = tiles[subtile] else { return FaceParts::empty() };
+        let next = subtile.tile_at_face(face);
+        let hidden = tile.is_hidden_by(tiles[next]);
+        let shapes = [subtile, next].map(|subtile| tiles[subtile].map_or(Shape::Cube, Tile::shape));
+        if shapes == [Shape::Cube; 2] {
+            // Cubes fill the whole face, so there is no need to check each part
+            return match hidden {
+                true => FaceParts::empty(),
+                false => FaceParts::all(),
+            };
+        }
+        let inward = subtile.into_tile(face.subtile_axis()) * SAMPLE_DEPTH;
+        FaceParts::points(face, subtile, 0.0)
+            .into_iter()
+            .filter(|&(_, point)| {
+                Tile::fills_at(tiles, subtile, point + inward)
+                    && !(hidden && Tile::fills_at(tiles, next, point - inward))
+            })
+            .fold(FaceParts::empty(), |parts, (part, _)| parts | part)
+    }
+
+    /// Axes of the faces of the tile at `subtile` that are not hidden at all.
+    /// Faces against shaped tiles that hide them are never exposed, as they
+    /// are hidden differently at each corner and the edges along them must
+    /// not change halfway
+    fn exposed_faces(tiles: &CornerTiles, subtile: Subtile) -> Subtile {
+        let Some(tile) = tiles[subtile] else { return Subtile::empty() };
+        SubtileFace::faces()
+            .filter(|&face| {
+                let next = tiles[subtile.tile_at_face(face)];
+                let shaped = matches!(next, Some(next) if next.shape() != Shape::Cube);
+                !(shaped && tile.is_hidden_by(next))
+                    && Tile::visible_parts(tiles, subtile, face).is_all()
+            })
+            .fold(Subtile::empty(), |exposed, face| {
+                exposed | face.subtile_axis()
+            })
+    }
+
+    /// Parts of the side of the tile at `subtile` facing along `face` through
+    /// the middle of the tile, that the subtile fills but the rest of the tile
+    /// past it does not
+    fn step_parts(tiles: &CornerTiles, subtile: Subtile, face: SubtileFace) -> FaceParts {
+        let step = subtile.into_tile(face.subtile_axis()) * SAMPLE_DEPTH;
+        FaceParts::points(face, subtile, 0.5)
+            .into_iter()
+            .filter(|&(_, point)| {
+                Tile::fills_at(tiles, subtile, point - step)
+                    && !Tile::fills_at(tiles, subtile, point + step)
+            })
+            .fold(FaceParts::empty(), |parts, (part, _)| parts | part)
+    }
+
     pub fn generate_mesh(tiles: &CornerTiles, subtile: Subtile, mesh: &mut MeshBuilder) {
-        let tile = tiles[subtile];
-        match tile {
-            None => {}
-            Some(Tile::Brick { color }) => {
-                let uv = color.uv();
-                for face in SubtileFace::faces() {
-                    let adj_pos = subtile.tile_at_face(face);
-                    let adj_tile = tiles[adj_pos];
-                    if !Tile::is_solid(adj_tile) {
-                        mesh.add(
-                            Face::Wall(
-                                WallFlags::CONNECTED_ALL
-                                    .set_facing_positive(subtile, face.subtile_axis()),
-                            ),
-                            uv,
-                            subtile,
-                            face,
-                        );
-                    }
+        let Some(tile) = tiles[subtile] else { return };
+        // Turned tiles are meshed upright among the tiles around them turned
+        // the other way, and their geometry is turned back into place
+        let orientation = tile.orientation();
+        let tiles = &tiles.unapply(orientation);
+        let subtile = Subtile::from_offset(orientation.unapply(subtile.offset()));
+        mesh.set_orientation(orientation);
+        Tile::generate_upright_mesh(tile, tiles, subtile, mesh);
+        mesh.set_orientation(Orientation::DEFAULT);
+    }
+
+    fn generate_upright_mesh(
+        tile: Tile,
+        tiles: &CornerTiles,
+        subtile: Subtile,
+        mesh: &mut MeshBuilder,
+    ) {
+        let uv = tile.color().uv();
+        mesh.set_layer(tile.mesh_layer());
+        // Water is only full if something covers it
+        let above = tiles[subtile | Subtile::Y];
+        let liquid = tile.is_water()
+            && tile.shape() == Shape::Cube
+            && !subtile.contains(Subtile::Y)
+            && !(Tile::is_solid(above) || matches!(above, Some(Tile::Water { .. })));
+        let edge = tile.edge();
+        let exposed = Tile::exposed_faces(tiles, subtile);
+
+        for face in SubtileFace::faces() {
+            let axis = face.subtile_axis();
+            if !exposed.contains(axis) {
+                let parts = Tile::visible_parts(tiles, subtile, face);
+                if !parts.is_empty() {
+                    mesh.add(Face::Partial(parts), uv, subtile, face);
+                }
+                continue;
+            }
+            let flags = match tile.has_mortar() {
+                true => Tile::connections(tiles, subtile, face),
+                false => WallFlags::CONNECTED_ALL,
+            }
+            .set_facing_positive(subtile, axis);
+            let rounded = exposed - axis;
+            let object = if liquid {
+                Face::Liquid(WATER_DEPTH)
+            } else if edge != Edge::None && !rounded.is_empty() {
+                Face::RoundedWall(flags, rounded)
+            } else {
+                Face::Wall(flags)
+            };
+            mesh.add(object, uv, subtile, face);
+        }
+
+        if tile.shape() != Shape::Cube {
+            for face in SubtileFace::faces() {
+                let parts = Tile::step_parts(tiles, subtile, face);
+                if !parts.is_empty() {
+                    mesh.add(Step(parts), uv, subtile, face);
                 }
             }
+            let planes = tile.shape().planes();
+            for &plane in planes {
+                mesh.add(Slope(planes), uv, subtile, plane);
+            }
+        }
+
+        if edge == Edge::None {
+            return;
+        }
+        mesh.add(edge, uv, subtile, exposed);
+        for subtile_edge in SubtileEdge::EDGES {
+            let [a, b, along] = subtile_edge.axes();
+            if exposed != a | b {
+                continue;
+            }
+            // The face along `along` is hidden, so there is a tile there
+            let next = subtile ^ along;
+            let next_tile = tiles[next].unwrap();
+            let continues =
+                next_tile.edge() == edge && Tile::exposed_faces(tiles, next).contains(a | b);
+            if !continues {
+                mesh.add(EdgeCap(edge), next_tile.color().uv(), subtile, subtile_edge);
+            }
         }
     }
 }
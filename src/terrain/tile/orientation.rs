@@ -0,0 +1,240 @@
+use std::fmt::{self, Display};
+
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Widget};
+
+/// Directions a tile can face, in the order of `Orientation::facing`
+const FACINGS: [IVec3; 6] = [
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+const FACING_NAMES: [&str; 6] = ["Up", "Down", "+X", "-X", "+Z", "-Z"];
+
+/// Which way a tile is turned.  The top of the tile faces one of six
+/// directions, and the tile is turned a number of quarter turns around it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Orientation(u8);
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Orientation {
+    /// Upright and unturned, which leaves tiles as they are
+    pub const DEFAULT: Self = Self(0);
+
+    /// How many different orientations there are
+    pub const COUNT: u8 = 24;
+
+    /// Orientation with the top facing `FACINGS[facing]`, turned `rotation`
+    /// quarter turns counterclockwise looking down on it
+    ///
+    /// # Panics
+    ///
+    /// Panics if `facing` is above 5 or `rotation` is above 3
+    pub fn new(facing: u8, rotation: u8) -> Self {
+        assert!(facing < 6 && rotation < 4);
+        Self(facing * 4 + rotation)
+    }
+
+    pub fn index(self) -> u8 {
+        self.0
+    }
+
+    pub fn from_index(i: u8) -> Option<Self> {
+        (i < Self::COUNT).then_some(Self(i))
+    }
+
+    /// Index into `FACINGS` of the direction the top of the tile faces
+    pub fn facing(self) -> u8 {
+        self.0 / 4
+    }
+
+    pub fn rotation(self) -> u8 {
+        self.0 % 4
+    }
+
+    /// Directions the x, y and z axes of the tile point in
+    fn axes(self) -> [IVec3; 3] {
+        let up = FACINGS[self.facing() as usize];
+        let mut front = match up.z {
+            0 => IVec3::Z,
+            _ => IVec3::Y,
+        };
+        for _ in 0..self.rotation() {
+            front = up.cross(front);
+        }
+        [up.cross(front), up, front]
+    }
+
+    /// The orientation with the given axes, which must be a rotation
+    fn from_axes([_, up, front]: [IVec3; 3]) -> Self {
+        let facing = FACINGS.iter().position(|&f| f == up).unwrap() as u8;
+        (0..4)
+            .map(|rotation| Self::new(facing, rotation))
+            .find(|orientation| orientation.axes()[2] == front)
+            .unwrap()
+    }
+
+    /// Turns self a quarter turn counterclockwise around `axis`, looking
+    /// from where it points
+    pub fn rotated(self, axis: IVec3) -> Self {
+        Self::from_axes(
+            self.axes()
+                .map(|dir| axis.cross(dir) + axis * axis.dot(dir)),
+        )
+    }
+
+    /// This orientation as seen from a tile turned to `other`
+    pub fn relative_to(self, other: Orientation) -> Self {
+        Self::from_axes(
+            self.axes()
+                .map(|axis| other.unapply(axis.as_vec3()).as_ivec3()),
+        )
+    }
+
+    /// Moves `v` from the space of the tile to the space around it
+    pub fn apply(self, v: Vec3) -> Vec3 {
+        let [x, y, z] = self.axes().map(|axis| axis.as_vec3());
+        // Adding zero turns negative zeros positive, so the same vertex always
+        // has the same bits
+        x * v.x + y * v.y + z * v.z + Vec3::ZERO
+    }
+
+    /// Moves `v` from the space around the tile to the space of the tile
+    pub fn unapply(self, v: Vec3) -> Vec3 {
+        let [x, y, z] = self.axes().map(|axis| axis.as_vec3());
+        Vec3::new(x.dot(v), y.dot(v), z.dot(v)) + Vec3::ZERO
+    }
+}
+
+impl Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let facing = FACING_NAMES[self.facing() as usize];
+        write!(f, "{facing} {}°", u16::from(self.rotation()) * 90)
+    }
+}
+
+impl Widget for &mut Orientation {
+    fn ui(self, ui: &mut egui::Ui) -> egui::Response {
+        let mut facing = self.facing();
+        let mut rotation = self.rotation();
+        let mut response = egui::ComboBox::from_label("Facing")
+            .selected_text(FACING_NAMES[facing as usize])
+            .show_ui(ui, |ui| {
+                for (i, name) in FACING_NAMES.into_iter().enumerate() {
+                    ui.selectable_value(&mut facing, i as u8, name);
+                }
+            })
+            .response;
+        response |= ui
+            .horizontal(|ui| {
+                let mut response = ui.label("Rotation");
+                for r in 0..4 {
+                    let degrees = u16::from(r) * 90;
+                    response |= ui.selectable_value(&mut rotation, r, format!("{degrees}°"));
+                }
+                response
+            })
+            .inner;
+        response |= ui
+            .horizontal(|ui| {
+                let mut response = ui.label("Turn");
+                for (axis, name) in [(IVec3::X, "X"), (IVec3::Y, "Y"), (IVec3::Z, "Z")] {
+                    let button = ui
+                        .button(name)
+                        .on_hover_text(format!("Quarter turn around the {name} axis"));
+                    if button.clicked() {
+                        let turned = self.rotated(axis);
+                        (facing, rotation) = (turned.facing(), turned.rotation());
+                    }
+                    response |= button;
+                }
+                response
+            })
+            .inner;
+        let picked = Orientation::new(facing, rotation);
+        if picked != *self {
+            *self = picked;
+            response.mark_changed();
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use bevy::prelude::*;
+    use proptest::prelude::*;
+
+    use super::Orientation;
+
+    prop_compose! {
+        pub fn arb_orientation()(i in 0..Orientation::COUNT) -> Orientation {
+            Orientation::from_index(i).unwrap()
+        }
+    }
+
+    fn arb_axis() -> impl Strategy<Value = IVec3> {
+        prop::sample::select(&[IVec3::X, IVec3::Y, IVec3::Z, IVec3::NEG_X][..])
+    }
+
+    #[test]
+    fn orientations_distinct() {
+        let mut axes = (0..Orientation::COUNT)
+            .map(|i| Orientation::from_index(i).unwrap().axes())
+            .collect::<Vec<_>>();
+        for [x, y, z] in &axes {
+            // Right handed, so nothing is mirrored
+            assert_eq!(x.cross(*y), *z);
+        }
+        axes.sort_by_key(|axes| axes.map(|axis| axis.to_array()));
+        axes.dedup();
+        assert_eq!(axes.len(), Orientation::COUNT as usize);
+        assert_eq!(Orientation::DEFAULT.axes(), [IVec3::X, IVec3::Y, IVec3::Z]);
+    }
+
+    proptest! {
+        #[test]
+        fn rotate_four_times(orientation in arb_orientation(), axis in arb_axis()) {
+            let mut rotated = orientation;
+            for turns in 1..=4 {
+                rotated = rotated.rotated(axis);
+                prop_assert_eq!(rotated == orientation, turns == 4);
+            }
+        }
+
+        #[test]
+        fn apply_unapply(orientation in arb_orientation(), v in prop::array::uniform3(-2.0f32..2.0)) {
+            let v = Vec3::from(v);
+            prop_assert_eq!(orientation.unapply(orientation.apply(v)), v);
+            prop_assert_eq!(Orientation::DEFAULT.apply(v), v);
+        }
+
+        #[test]
+        fn relative_orientations(a in arb_orientation(), b in arb_orientation(), v in prop::array::uniform3(-2.0f32..2.0)) {
+            let v = Vec3::from(v);
+            prop_assert_eq!(a.relative_to(a), Orientation::DEFAULT);
+            prop_assert_eq!(b.apply(a.relative_to(b).apply(v)), a.apply(v));
+        }
+
+        #[test]
+        fn rotated_turns_axes(orientation in arb_orientation(), axis in arb_axis()) {
+            // A quarter turn around y takes z to x
+            let turned = orientation.rotated(IVec3::Y);
+            let before = orientation.apply(Vec3::Z);
+            prop_assert_eq!(turned.apply(Vec3::Z), Vec3::new(before.z, before.y, -before.x) + Vec3::ZERO);
+            // Turning around an axis leaves it where it is
+            let along = axis.as_vec3();
+            let local = orientation.unapply(along);
+            prop_assert_eq!(orientation.rotated(axis).apply(local), along);
+        }
+    }
+}
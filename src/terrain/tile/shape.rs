@@ -0,0 +1,129 @@
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Widget};
+
+/// Normal of a slope facing up and to the front
+const FRONT_SLOPE: Vec3 = Vec3::new(0.0, 1.0, 1.0);
+/// Normal of a slope facing up and to +x
+const SIDE_SLOPE: Vec3 = Vec3::new(1.0, 1.0, 0.0);
+
+/// The part of its tile a tile fills.  Shapes are described upright and
+/// unturned, with the front of the tile facing +z
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Shape {
+    #[default]
+    Cube,
+    /// A ramp rising from the bottom of the front to the top of the back
+    Slope,
+    /// Where two slopes meet at the outer corner of a roof, rising to the top
+    /// of the edge between the back and the -x side
+    SlopeCorner,
+    /// Two steps climbing from the front to the back
+    Stairs,
+}
+
+impl Shape {
+    pub const SHAPES: [Self; 4] = [Self::Cube, Self::Slope, Self::SlopeCorner, Self::Stairs];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Shape::Cube => "Cube",
+            Shape::Slope => "Slope",
+            Shape::SlopeCorner => "Slope Corner",
+            Shape::Stairs => "Stairs",
+        }
+    }
+
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_index(i: u8) -> Option<Self> {
+        Self::SHAPES.get(usize::from(i)).copied()
+    }
+
+    /// Normals of the sloped surfaces of the shape.  Each passes through the
+    /// center of the tile, and the shape only fills the space behind them
+    pub fn planes(self) -> &'static [Vec3] {
+        match self {
+            Shape::Slope => &[FRONT_SLOPE],
+            Shape::SlopeCorner => &[FRONT_SLOPE, SIDE_SLOPE],
+            Shape::Cube | Shape::Stairs => &[],
+        }
+    }
+
+    /// Whether the shape fills `point`, relative to the center of the tile.
+    /// Points on the surface of the shape may go either way
+    pub fn contains(self, point: Vec3) -> bool {
+        match self {
+            Shape::Stairs => point.y < 0.0 || point.z < 0.0,
+            _ => self.planes().iter().all(|normal| normal.dot(point) < 0.0),
+        }
+    }
+}
+
+impl Widget for &mut Shape {
+    fn ui(self, ui: &mut egui::Ui) -> egui::Response {
+        let mut picked = *self;
+        let mut response = egui::ComboBox::from_label("Shape")
+            .selected_text(self.name())
+            .show_ui(ui, |ui| {
+                for shape in Shape::SHAPES {
+                    ui.selectable_value(&mut picked, shape, shape.name());
+                }
+            })
+            .response;
+        if picked != *self {
+            *self = picked;
+            response.mark_changed();
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use bevy::prelude::*;
+    use itertools::iproduct;
+    use proptest::prelude::*;
+
+    use super::Shape;
+
+    prop_compose! {
+        pub fn arb_shape()(i in 0..Shape::SHAPES.len()) -> Shape {
+            Shape::SHAPES[i]
+        }
+    }
+
+    /// Fraction of points spread evenly through the tile the shape fills
+    fn volume(shape: Shape) -> f32 {
+        let steps = -7..7;
+        let points = iproduct!(steps.clone(), steps.clone(), steps)
+            .map(|(x, y, z)| (Vec3::new(x as f32, y as f32, z as f32) + 0.5) / 14.0);
+        let filled = points.clone().filter(|&p| shape.contains(p)).count();
+        filled as f32 / points.count() as f32
+    }
+
+    #[test]
+    fn shapes_fill_part_of_tile() {
+        assert_eq!(volume(Shape::Cube), 1.0);
+        assert!((volume(Shape::Slope) - 0.5).abs() < 0.05);
+        assert!(volume(Shape::SlopeCorner) < volume(Shape::Slope));
+        assert_eq!(volume(Shape::Stairs), 0.75);
+
+        // Everything leans towards the bottom of the back
+        for shape in Shape::SHAPES {
+            assert!(shape.contains(Vec3::new(-0.25, -0.25, -0.25)));
+        }
+        assert!(!Shape::Slope.contains(Vec3::new(0.0, 0.25, 0.25)));
+        assert!(!Shape::Stairs.contains(Vec3::new(0.0, 0.25, 0.25)));
+    }
+
+    #[test]
+    fn shape_indices() {
+        for (i, shape) in Shape::SHAPES.into_iter().enumerate() {
+            assert_eq!(shape.index() as usize, i);
+            assert_eq!(Shape::from_index(shape.index()), Some(shape));
+        }
+        assert_eq!(Shape::from_index(Shape::SHAPES.len() as u8), None);
+    }
+}